@@ -4,6 +4,7 @@ use ethel::{
     DrawCommand, layout_buffer, layout_mesh_buffer,
     render::buffer::{PartitionedTriBuffer, TriBuffer},
 };
+use physics::snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter};
 
 pub const RENDER_STORAGE_PARTS: usize = 8;
 pub const ENTITY_ALLOCATION: usize = 8192;
@@ -16,6 +17,20 @@ pub struct Renderable {
     pub data_handle: u32,
 }
 
+impl Snapshot for Renderable {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.mesh_id);
+        writer.write(&self.data_handle);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self {
+            mesh_id: reader.read()?,
+            data_handle: reader.read()?,
+        })
+    }
+}
+
 layout_mesh_buffer!(count: 512; vertices: 2048);
 
 layout_buffer! {
@@ -85,7 +100,7 @@ layout_buffer! {
 }
 
 pub const FRAGMENTS_ALLOC: usize = 16384;
-pub const FRAGMENTS_DATA_PARTS: usize = 7;
+pub const FRAGMENTS_DATA_PARTS: usize = 10;
 
 layout_buffer! {
     const FragmentData: FRAGMENTS_DATA_PARTS, {
@@ -125,6 +140,22 @@ layout_buffer! {
             bind 6;
             shader 8;
         };
+
+        enum PodPositions: FRAGMENTS_ALLOC => {
+            type [f32; 4];
+            bind 7;
+            shader 4;
+        };
+        enum PodRotations: FRAGMENTS_ALLOC => {
+            type [f32; 4];
+            bind 8;
+            shader 5;
+        };
+        enum PodHealth: FRAGMENTS_ALLOC => {
+            type f32;
+            bind 9;
+            shader 9;
+        };
     }
 }
 
@@ -9,10 +9,13 @@ use crate::{
     state::physics::XpbdSystem,
     structure::{
         self, FragmentSystem,
-        fragment::{VoxelGrid, VoxelGridOptions},
+        fragment::{DebrisOptions, FragmentState, VoxelGrid, VoxelGridOptions},
     },
 };
-use ::physics::xpbd::{LatticeIds, XpbdLatticeBuilder, XpbdOptions, XpbdSolver};
+use ::physics::{
+    snapshot::{HandleMaps, Snapshot, SnapshotError, SnapshotReader, SnapshotWriter},
+    xpbd::{LatticeIds, XpbdLatticeBuilder, XpbdOptions, XpbdSolver},
+};
 use ethel::{
     render::{ScreenSpace, command::DrawArraysIndirectCommand},
     state::{
@@ -31,6 +34,10 @@ ethel::table_spec! {
 }
 
 const GROUND_LEVEL: f32 = -15.0;
+const WIND_FORCE: f32 = 1.0;
+
+/// Format version of the snapshots written by [`State::save_snapshot`].
+pub const SNAPSHOT_VERSION: u32 = 1;
 
 #[derive(Debug)]
 pub struct State {
@@ -47,6 +54,9 @@ pub struct State {
     /// Selected xpbd link id
     selection: Option<u32>,
 
+    /// Node positions interpolated between the last two physics steps
+    node_positions: Vec<glam::Vec3>,
+
     camera: camera::Orbital,
 }
 
@@ -54,19 +64,62 @@ const CAMERA_YAW_CLAMP: std::ops::Range<f32> = f32::NEG_INFINITY..f32::INFINITY;
 const CAMERA_PITCH_CLAMP: std::ops::Range<f32> =
     -std::f32::consts::FRAC_PI_2..std::f32::consts::FRAC_PI_2;
 
+impl Snapshot for EntityDataRowTable {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&HandleMaps::new(
+            self.handles(),
+            self.slots_map(),
+            self.free_list(),
+        ));
+        writer.write_slice(self.position_slice());
+        writer.write_slice(self.rotation_slice());
+        writer.write_slice(self.scale_slice());
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        let maps = reader.read::<HandleMaps>()?;
+        let position = reader.read_vec::<glam::Vec4>()?;
+        let rotation = reader.read_vec::<glam::Quat>()?;
+        let scale = reader.read_vec::<glam::Vec4>()?;
+
+        maps.check_column(&position)?;
+        maps.check_column(&rotation)?;
+        maps.check_column(&scale)?;
+
+        // skip degenerate; the fresh table has its own
+        let mut table = Self::with_capacity(maps.len());
+        for i in 1..maps.len() {
+            table.put((position[i], rotation[i], scale[i]));
+        }
+        let (handles, slots_map, free_list) = maps.into_parts();
+        table.handles_mut().copy_from_slice(&handles);
+        *table.slots_map_mut() = slots_map;
+        *table.free_list_mut() = free_list;
+        Ok(table)
+    }
+}
+
 impl Default for State {
     fn default() -> Self {
         Self {
             xpbd: XpbdSystem::new(XpbdSolver::new(
-                XpbdOptions::default().with_ground_level(Some(GROUND_LEVEL)),
+                XpbdOptions::default()
+                    .with_ground_level(Some(GROUND_LEVEL))
+                    .with_gravity(glam::vec3(WIND_FORCE, -9.81, WIND_FORCE)),
             )),
 
-            fragments: Default::default(),
+            fragments: {
+                let mut fragments = FragmentSystem::new();
+                fragments
+                    .set_debris_options(DebrisOptions::default().with_ground_level(GROUND_LEVEL));
+                fragments
+            },
             renderables: Default::default(),
             mesh_ids: Default::default(),
             entity_data: Default::default(),
             frag_map: Default::default(),
             selection: Default::default(),
+            node_positions: Default::default(),
             camera: camera::Orbital::new(
                 Default::default(),
                 Default::default(),
@@ -94,6 +147,8 @@ impl ethel::StateHandler<FrameDataBuffers> for State {
         //     base_instance: 0,
         // });
 
+        self.xpbd.interpolated_positions(&mut self.node_positions);
+
         let fragment_count = self.fragments.table().len() as u32;
         command_queue.push(DrawArraysIndirectCommand {
             count: 36,
@@ -109,13 +164,16 @@ impl ethel::StateHandler<FrameDataBuffers> for State {
             {
                 let fragments = &storage.fragments;
 
-                let imap_nodes = self.xpbd.nodes().handles();
-                let pod_nodes_positions = self.xpbd.nodes().current_pos_slice();
+                let imap_nodes = self.xpbd.nodes().slots_map();
+                let pod_nodes_positions = self.node_positions.as_slice();
                 let pod_nodes_rotors = self.xpbd.rotor_system().rotations();
                 let pod_parents = self.fragments.table().parents_slice();
                 let pod_weights = self.fragments.table().influence_slice();
                 let pod_offsets = self.fragments.table().rest_offset_slice();
                 let pod_states = self.fragments.table().state_slice();
+                let pod_positions = self.fragments.table().position_slice();
+                let pod_rotations = self.fragments.table().rotation_slice();
+                let pod_health = self.fragments.table().health_slice();
 
                 // SAFETY: the use of LayoutFragmentData ensures we are
                 // blitting to a valid section of the fragments partitioned
@@ -128,6 +186,9 @@ impl ethel::StateHandler<FrameDataBuffers> for State {
                     fragments.blit_part(buf_idx, LayoutFragmentData::PodWeights as usize, pod_weights, 0);
                     fragments.blit_part_padded(buf_idx, LayoutFragmentData::PodOffsets as usize, pod_offsets, 0, 4);
                     fragments.blit_part(buf_idx, LayoutFragmentData::PodStates as usize, pod_states, 0);
+                    fragments.blit_part_padded(buf_idx, LayoutFragmentData::PodPositions as usize, pod_positions, 0, 4);
+                    fragments.blit_part(buf_idx, LayoutFragmentData::PodRotations as usize, pod_rotations, 0);
+                    fragments.blit_part(buf_idx, LayoutFragmentData::PodHealth as usize, pod_health, 0);
                 }
             }
 
@@ -151,7 +212,7 @@ impl ethel::StateHandler<FrameDataBuffers> for State {
                     );
                 }
 
-                let imap_entity_data = self.entity_data.handles();
+                let imap_entity_data = self.entity_data.slots_map();
                 let pod_positions = self.entity_data.position_slice();
                 let pod_rotations = self.entity_data.rotation_slice();
                 let pod_scales = self.entity_data.scale_slice();
@@ -186,8 +247,8 @@ impl ethel::StateHandler<FrameDataBuffers> for State {
 
                 let xpbd_dbg = &storage.xpbd_debug;
                 let constraints = self.xpbd.links().relation_slice();
-                let imap_nodes = self.xpbd.nodes().handles();
-                let pod_nodes = self.xpbd.nodes().current_pos_slice();
+                let imap_nodes = self.xpbd.nodes().slots_map();
+                let pod_nodes = self.node_positions.as_slice();
                 let selected_link = {
                     let handle = self.selection.unwrap_or_default();
                     self.xpbd.links().get_indirect(handle).unwrap_or_default()
@@ -296,31 +357,21 @@ impl ethel::StateHandler<FrameDataBuffers> for State {
             });
         }
 
-        const WIND_FORCE: f32 = 1.0;
-        self.xpbd
-            .apply_forces_batched(glam::vec3(WIND_FORCE, -9.81, WIND_FORCE));
-
         {
-            let broken_links = self.xpbd.frame_broken_links();
-            self.fragments
-                .handle_constraint_break(broken_links, self.xpbd.links());
-
-            let broken_frags = self.fragments.frame_disabled_frags_direct();
-            for &broken in broken_frags {
-                let renderable_id = *unsafe { self.frag_map.get_unchecked(broken as usize) };
-                let entity_id = self.renderables[renderable_id as usize].data_handle;
-                let e_index = unsafe { self.entity_data.get_indirect_unchecked(entity_id) };
-                let pos = unsafe {
-                    self.entity_data
-                        .position_mut_slice()
-                        .get_unchecked_mut(e_index as usize)
-                };
+            self.fragments.handle_constraint_break(&self.xpbd);
+            self.fragments.update_damage(&self.xpbd, delta);
+            self.fragments.release_debris(&self.xpbd);
+        }
 
-                pos.w = 0.0;
-            }
+        // debris follows the fixed steps of the lattice
+        let steps = self.xpbd.update(delta);
+        let step_time = self.xpbd.fixed_step().step_time();
+        for _ in 0..steps {
+            self.fragments.integrate_debris(step_time);
         }
+        self.sync_debris_renderables();
 
-        self.xpbd.update(delta);
+        self.free_fragments_with(|state| state.fragments.despawn_debris(view_point.position));
 
         // random demo
         if input.keys().key_pressed(janus::input::KeyCode::KeyH) {
@@ -363,6 +414,55 @@ impl ethel::StateHandler<FrameDataBuffers> for State {
 }
 
 impl State {
+    /// Save the simulation state into a versioned binary snapshot.
+    ///
+    /// This covers the [`XpbdSystem`], including its rotors, the
+    /// [`FragmentSystem`], and the renderables fragments are mapped to.
+    /// Camera and selection are not part of the snapshot.
+    pub fn save_snapshot(&self) -> Vec<u8> {
+        let mut writer = SnapshotWriter::new(SNAPSHOT_VERSION);
+        writer.write(&self.xpbd);
+        writer.write(&self.fragments);
+        writer.write(&self.renderables);
+        writer.write(&self.entity_data);
+        writer.write(&self.frag_map);
+        writer.finish()
+    }
+
+    /// Restore the simulation state from a snapshot written by
+    /// [`State::save_snapshot`].
+    ///
+    /// Handles taken before the snapshot was saved are valid again once it
+    /// is restored. The selection is cleared.
+    ///
+    /// # Errors
+    /// Will return an error if `bytes` is not a snapshot of the current
+    /// [`SNAPSHOT_VERSION`], or if it is truncated or corrupt. The state is
+    /// left untouched on error.
+    pub fn restore_snapshot(&mut self, bytes: &[u8]) -> Result<(), SnapshotError> {
+        let mut reader = SnapshotReader::new(bytes)?;
+        if reader.version() != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(reader.version()));
+        }
+
+        let xpbd = reader.read()?;
+        let fragments = reader.read()?;
+        let renderables = reader.read()?;
+        let entity_data = reader.read()?;
+        let frag_map = reader.read()?;
+        if !reader.is_empty() {
+            return Err(SnapshotError::Corrupt("trailing bytes"));
+        }
+
+        self.xpbd = xpbd;
+        self.fragments = fragments;
+        self.renderables = renderables;
+        self.entity_data = entity_data;
+        self.frag_map = frag_map;
+        self.selection = None;
+        Ok(())
+    }
+
     pub fn create_renderable(
         &mut self,
         mesh_id: u32,
@@ -384,6 +484,35 @@ impl State {
         id as u32
     }
 
+    /// Move the renderables of debris fragments to their fragment.
+    ///
+    /// Renderables are not drawn for now, see
+    /// [`State::register_structure`], but each fragment keeps one: they
+    /// stay in place for when fragments are drawn as renderables.
+    fn sync_debris_renderables(&mut self) {
+        let table = self.fragments.table();
+        let transforms = table
+            .state_slice()
+            .iter()
+            .zip(table.position_slice())
+            .zip(table.rotation_slice())
+            .enumerate()
+            // skip degenerate
+            .skip(1);
+
+        for (frag_idx, ((&state, &position), &rotation)) in transforms {
+            if state != FragmentState::Debris {
+                continue;
+            }
+            let renderable_id = *unsafe { self.frag_map.get_unchecked(frag_idx) };
+            let entity_id = self.renderables[renderable_id as usize].data_handle;
+            let e_index = unsafe { self.entity_data.get_indirect_unchecked(entity_id) } as usize;
+
+            self.entity_data.position_mut_slice()[e_index] = position.extend(1.0);
+            self.entity_data.rotation_mut_slice()[e_index] = rotation;
+        }
+    }
+
     pub fn register_structure(
         &mut self,
         voxel_grid: &VoxelGrid,
@@ -443,4 +572,169 @@ impl State {
 
         lattice_map
     }
+
+    /// Despawn a structure registered with [`State::register_structure`].
+    ///
+    /// This frees its nodes and links, their rotors, and its fragments along
+    /// with the renderables they are mapped to. Renderable IDs created after
+    /// the removed renderables are shifted down to stay contiguous.
+    ///
+    /// The handles in `lattice` must not be used once this returns.
+    pub fn unregister_structure(&mut self, lattice: LatticeIds) {
+        self.free_fragments_with(|state| {
+            let owned_nodes = lattice.owned_nodes().collect::<Vec<_>>();
+            state.fragments.remove_nodes(&owned_nodes);
+            state.xpbd.remove_lattice(&lattice);
+        });
+
+        if self
+            .selection
+            .is_some_and(|link| self.xpbd.links().get_indirect(link).is_none())
+        {
+            self.selection = None;
+        }
+    }
+
+    /// Run `free`, which may free fragments, and keep the fragment map and
+    /// the renderables of fragments in line with the fragments table.
+    fn free_fragments_with<R>(&mut self, free: impl FnOnce(&mut Self) -> R) -> R {
+        // the fragment map is parallel to the fragments table, and must
+        // follow the rows moved by freeing
+        let old_handles = self.fragments.table().handles().to_vec();
+        let old_slots = self.fragments.table().slots_map().to_vec();
+
+        let result = free(self);
+        if self.fragments.table().handles().len() != old_handles.len() {
+            self.compact_fragment_map(&old_handles, &old_slots);
+        }
+        result
+    }
+
+    /// Follow the fragments freed or moved since the fragments table had
+    /// `old_handles` and `old_slots`: the fragment map is remapped, and the
+    /// renderables of freed fragments are freed.
+    ///
+    /// Renderable IDs created after freed renderables are shifted down to
+    /// stay contiguous.
+    fn compact_fragment_map(&mut self, old_handles: &[u32], old_slots: &[u32]) {
+        if self.frag_map.is_empty() {
+            return;
+        }
+
+        let table = self.fragments.table();
+        let mut removed = vec![false; self.renderables.len()];
+        for (&handle, &renderable_id) in old_handles.iter().zip(&self.frag_map).skip(1) {
+            if table.get_indirect(handle).is_none() {
+                removed[renderable_id as usize] = true;
+            }
+        }
+        self.frag_map = table
+            .handles()
+            .iter()
+            .map(|&handle| self.frag_map[old_slots[handle as usize] as usize])
+            .collect();
+
+        let mut renderable_map = Vec::with_capacity(removed.len());
+        let renderables = std::mem::take(&mut self.renderables);
+        for (renderable, removed) in renderables.into_iter().zip(removed) {
+            if removed {
+                self.entity_data.free(renderable.data_handle);
+                renderable_map.push(0);
+            } else {
+                renderable_map.push(self.renderables.len() as u32);
+                self.renderables.push(renderable);
+            }
+        }
+        self.frag_map
+            .iter_mut()
+            .for_each(|id| *id = renderable_map[*id as usize]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::physics::xpbd::{XpbdLinkOptions, XpbdNodeOptions};
+
+    /// A fixed cube lattice of side 2 around `center`, and the voxel grid of
+    /// side 1 that fills it.
+    fn cube_structure(center: glam::Vec3) -> (VoxelGrid, XpbdLatticeBuilder) {
+        let mut builder = XpbdLatticeBuilder::new();
+        let ids = (0..8)
+            .map(|i| {
+                let corner = glam::vec3(
+                    if i & 1 == 0 { -1.0 } else { 1.0 },
+                    if i & 2 == 0 { -1.0 } else { 1.0 },
+                    if i & 4 == 0 { -1.0 } else { 1.0 },
+                );
+                builder.node(XpbdNodeOptions::new(center + corner, 1.0).with_fixed(true))
+            })
+            .collect::<Vec<_>>();
+        for (i, &a) in ids.iter().enumerate() {
+            for &b in &ids[i + 1..] {
+                builder.link_nodes(a, b, XpbdLinkOptions::new(0.0));
+            }
+        }
+
+        let mut grid = VoxelGrid::new(|_| true, VoxelGridOptions::new(1.0, 1.0, 1.0, 2));
+        grid.build(center + glam::Vec3::splat(0.25));
+        (grid, builder)
+    }
+
+    /// The position of the renderable each live fragment is mapped to.
+    fn renderable_positions(state: &State) -> Vec<(u32, glam::Vec3)> {
+        let table = state.fragments.table();
+        assert_eq!(state.frag_map.len(), table.handles().len());
+        assert_eq!(state.renderables.len(), table.handles().len() - 1);
+
+        // skip degenerate
+        let mut seen = vec![false; state.renderables.len()];
+        table.handles()[1..]
+            .iter()
+            .zip(&state.frag_map[1..])
+            .map(|(&handle, &renderable_id)| {
+                assert!(!std::mem::replace(&mut seen[renderable_id as usize], true));
+                let entity = state.renderables[renderable_id as usize].data_handle;
+                let e_index = state.entity_data.get_indirect(entity).unwrap() as usize;
+                (
+                    handle,
+                    state.entity_data.position_slice()[e_index].truncate(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unregister_structure_keeps_fragment_map() {
+        let mut state = State::default();
+
+        let (grid, lattice) = cube_structure(glam::Vec3::ZERO);
+        let first = state.register_structure(&grid, lattice);
+        let (grid, lattice) = cube_structure(glam::vec3(10.0, 0.0, 0.0));
+        state.register_structure(&grid, lattice);
+
+        // renderables are created at the position of their fragment
+        let before = renderable_positions(&state);
+        assert_eq!(before.len(), 16);
+        let table = state.fragments.table();
+        for &(handle, position) in &before {
+            let frag_idx = table.get_indirect(handle).unwrap() as usize;
+            assert_eq!(table.position_slice()[frag_idx], position);
+        }
+
+        state.unregister_structure(first);
+
+        // only the fragments of the second structure are left, each still
+        // mapped to its own renderable
+        let after = renderable_positions(&state);
+        let expected = before
+            .into_iter()
+            .filter(|&(_, position)| position.x > 5.0)
+            .collect::<Vec<_>>();
+        assert_eq!(after.len(), 8);
+        assert_eq!(state.entity_data.handles().len(), 9);
+        for (handle, position) in after {
+            assert!(expected.contains(&(handle, position)));
+        }
+    }
 }
@@ -2,10 +2,58 @@ pub mod rotor;
 
 use ethel::state::data::Column;
 use janus::context::DeltaTime;
-use physics::xpbd::{LinksRowTable, NodesRowTable, XpbdLatticeBuilder, XpbdSolver};
+use physics::{
+    island::{IslandTracker, SleepOptions},
+    snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter},
+    xpbd::{LinkNodes, LinksRowTable, NodesRowTable, XpbdLatticeBuilder, XpbdSolver},
+};
+use rustc_hash::FxHashSet;
 
 use crate::state::physics::rotor::RotorSystem;
 
+pub const DEFAULT_STEP_RATE: f32 = 60.0;
+pub const DEFAULT_MAX_STEPS: u32 = 4;
+
+/// Rate at which the simulation advances, independently of the frame rate.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FixedStep {
+    /// Simulation steps per second.
+    pub rate: f32,
+    /// Maximum amount of steps taken in a single frame.
+    ///
+    /// Time left over once this is reached is dropped: the simulation slows
+    /// down rather than taking ever longer frames to catch up.
+    pub max_steps: u32,
+}
+
+impl FixedStep {
+    pub const fn new(rate: f32, max_steps: u32) -> Self {
+        Self { rate, max_steps }
+    }
+
+    #[inline]
+    pub fn step_time(&self) -> f32 {
+        1.0 / self.rate
+    }
+}
+
+impl Default for FixedStep {
+    fn default() -> Self {
+        Self::new(DEFAULT_STEP_RATE, DEFAULT_MAX_STEPS)
+    }
+}
+
+impl Snapshot for FixedStep {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.rate);
+        writer.write(&self.max_steps);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self::new(reader.read()?, reader.read()?))
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct XpbdSystem {
     nodes: NodesRowTable,
@@ -13,6 +61,19 @@ pub struct XpbdSystem {
 
     solver: XpbdSolver,
     rotor_system: RotorSystem,
+    islands: IslandTracker,
+    sleep_options: SleepOptions,
+
+    fixed_step: FixedStep,
+    accumulator: f32,
+    alpha: f32,
+    /// Node positions before the last step; parallel to `current_pos`.
+    previous_pos: Vec<glam::Vec3>,
+
+    /// Links broken by hand, not yet reported
+    pending_breaks: Vec<(u32, LinkNodes)>,
+    frame_broken_links: Vec<u32>,
+    frame_broken_relations: Vec<LinkNodes>,
 }
 
 impl XpbdSystem {
@@ -29,6 +90,8 @@ impl XpbdSystem {
             nodes: NodesRowTable::with_capacity(capacity),
             links: LinksRowTable::with_capacity(capacity),
             rotor_system: RotorSystem::with_capacity(capacity),
+            previous_pos: Vec::with_capacity(capacity),
+            ..Default::default()
         }
     }
 
@@ -41,27 +104,124 @@ impl XpbdSystem {
         }
     }
 
+    /// Advance the simulation by the frame time `delta`.
+    ///
+    /// The simulation runs at the fixed rate of [`FixedStep`]: this takes as
+    /// many fixed steps as fit in the accumulated frame time, up to
+    /// [`FixedStep::max_steps`]. See [`XpbdSystem::interpolation_alpha`] for
+    /// rendering between steps.
+    ///
+    /// # Returns
+    /// The amount of fixed steps taken.
     #[inline]
-    pub fn update(&mut self, delta: DeltaTime) {
+    pub fn update(&mut self, delta: DeltaTime) -> u32 {
+        self.update_seconds(delta.as_f32())
+    }
+
+    /// Advance the simulation by `seconds` of frame time.
+    ///
+    /// See [`XpbdSystem::update`].
+    pub fn update_seconds(&mut self, seconds: f32) -> u32 {
         // todo: perf telemetry
-        self.solver.set_step_time(delta);
+        self.islands.clear_frame_splits();
+        self.frame_broken_links.clear();
+        self.frame_broken_relations.clear();
+
+        for (handle, relation) in self.pending_breaks.drain(..) {
+            self.frame_broken_links.push(handle);
+            self.frame_broken_relations.push(relation);
+        }
+        self.islands
+            .handle_broken_links(&self.frame_broken_links, &mut self.nodes);
+
+        let step_time = self.fixed_step.step_time();
+        self.accumulator += seconds;
+
+        let mut steps = 0;
+        while self.accumulator >= step_time {
+            if steps == self.fixed_step.max_steps {
+                self.accumulator %= step_time;
+                break;
+            }
+
+            self.step(step_time);
+            self.accumulator -= step_time;
+            steps += 1;
+        }
+        self.alpha = self.accumulator / step_time;
+
+        self.rotor_system.remove_links(&self.frame_broken_links);
+        if steps > 0 {
+            self.rotor_system
+                .recompute_rotations(&self.nodes, &self.links);
+        }
+        steps
+    }
+
+    #[inline]
+    fn step(&mut self, step_time: f32) {
+        self.previous_pos.clear();
+        self.previous_pos
+            .extend_from_slice(self.nodes.current_pos_slice());
+
+        self.solver.set_step_seconds(step_time);
         self.solver.step(&mut self.nodes, &mut self.links);
 
-        self.rotor_system
-            .recompute_relatives(&self.nodes, &self.links);
-        self.rotor_system.recompute_rotations(&self.nodes);
+        if self.solver.allow_breaking() {
+            let broken = self.solver.broken_links();
+            self.islands.handle_broken_links(broken, &mut self.nodes);
+
+            // broken links are only freed at the beginning of the next step
+            for &handle in broken {
+                let index = unsafe { self.links.get_indirect_unchecked(handle) };
+                self.frame_broken_links.push(handle);
+                self.frame_broken_relations
+                    .push(self.links.relation_slice()[index as usize]);
+            }
+        }
+        self.islands
+            .update_sleep(&mut self.nodes, &self.links, &self.sleep_options);
     }
 
     /// Break a `constraint` by its handle.
+    ///
+    /// This wakes up the island the constraint belongs to. The constraint is
+    /// reported as broken by the next [`XpbdSystem::update`].
     #[inline]
     pub fn break_constraint(&mut self, constraint: u32) {
-        if self.links.get_indirect(constraint).is_some() {
-            self.solver.break_link(constraint);
+        let Some(index) = self.links.get_indirect(constraint) else {
+            return;
+        };
+        if self.pending_breaks.iter().any(|&(h, _)| h == constraint) {
+            return;
         }
+
+        let relation = self.links.relation_slice()[index as usize];
+        self.islands.wake_node(relation.0, &mut self.nodes);
+        self.solver.break_link(constraint);
+        self.pending_breaks.push((constraint, relation));
     }
 
+    /// Wake up the island `node` belongs to.
+    ///
+    /// Applied forces and broken links already wake their island: call this
+    /// when anything else starts acting on a structure that may be asleep.
+    /// Debris does not collide with the lattice, so nothing wakes islands on
+    /// contact.
+    #[inline]
+    pub fn wake_node(&mut self, node: u32) {
+        self.islands.wake_node(node, &mut self.nodes);
+    }
+
+    #[inline]
+    pub fn set_sleep_options(&mut self, options: SleepOptions) {
+        self.sleep_options = options;
+    }
+
+    /// Apply `force` to the node `index`, waking up its island.
     #[inline]
     pub fn apply_forces(&mut self, index: u32, force: glam::Vec3) {
+        self.islands.wake_node(index, &mut self.nodes);
         if let Some(node) = self.nodes.get_indirect(index) {
             let mass = *unsafe { self.nodes.mass_slice().get_unchecked(node as usize) };
             let f = unsafe {
@@ -80,9 +240,15 @@ impl XpbdSystem {
         }
     }
 
+    /// Apply `force` to every node, scaled by its mass.
+    ///
+    /// This does not wake up sleeping islands, and sleeping nodes discard it.
+    /// Like any accumulated force, it is spent by the next step: use
+    /// [`XpbdSolver::set_gravity`] for constant ambient forces such as
+    /// gravity.
     #[inline]
     pub fn apply_forces_batched(&mut self, force: glam::Vec3) {
-        let (_, _, m, _, f, _) = self.nodes_mut().split_mut();
+        let (_, _, m, _, f, _, _, _) = self.nodes_mut().split_mut();
         for (f, m) in f.join(m) {
             *f += force * *m;
         }
@@ -103,6 +269,11 @@ impl XpbdSystem {
         &self.rotor_system
     }
 
+    #[inline]
+    pub fn islands(&self) -> &IslandTracker {
+        &self.islands
+    }
+
     #[inline]
     pub fn nodes_mut(&mut self) -> &mut NodesRowTable {
         &mut self.nodes
@@ -123,9 +294,95 @@ impl XpbdSystem {
         (&mut self.nodes, &mut self.links)
     }
 
+    /// Handles of the links broken during the last [`XpbdSystem::update`].
+    ///
+    /// These may already have been freed from the links table; see
+    /// [`XpbdSystem::frame_broken_relations`].
     #[inline]
     pub fn frame_broken_links(&self) -> &[u32] {
-        self.solver.broken_links()
+        &self.frame_broken_links
+    }
+
+    /// The nodes of the links broken during the last [`XpbdSystem::update`].
+    ///
+    /// This is parallel to [`XpbdSystem::frame_broken_links`].
+    #[inline]
+    pub fn frame_broken_relations(&self) -> &[LinkNodes] {
+        &self.frame_broken_relations
+    }
+
+    #[inline]
+    pub fn fixed_step(&self) -> FixedStep {
+        self.fixed_step
+    }
+
+    #[inline]
+    pub fn set_fixed_step(&mut self, fixed_step: FixedStep) {
+        self.fixed_step = fixed_step;
+    }
+
+    /// How far, as a fraction of a step, the frame time has run past the
+    /// last simulation step.
+    ///
+    /// Blend [`XpbdSystem::previous_pos_slice`] toward the current node
+    /// positions by this factor to render between steps.
+    #[inline]
+    pub fn interpolation_alpha(&self) -> f32 {
+        self.alpha
+    }
+
+    /// Node positions before the last simulation step.
+    ///
+    /// This is parallel to the current node positions, except for nodes
+    /// imported since the last step, which are missing from the end.
+    #[inline]
+    pub fn previous_pos_slice(&self) -> &[glam::Vec3] {
+        &self.previous_pos
+    }
+
+    /// Write the node positions interpolated between the last two steps into
+    /// `out`, parallel to the current node positions.
+    pub fn interpolated_positions(&self, out: &mut Vec<glam::Vec3>) {
+        let current = self.nodes.current_pos_slice();
+        out.clear();
+        out.extend(
+            current
+                .iter()
+                .enumerate()
+                .map(|(i, &p)| match self.previous_pos.get(i) {
+                    Some(&prev) => prev.lerp(p, self.alpha),
+                    None => p,
+                }),
+        );
+    }
+
+    /// Write the largest strain of the links of each node into `out`,
+    /// parallel to the current node positions.
+    ///
+    /// The strain of a link is how far its length is from its rest length,
+    /// relative to it: links that yielded plastically are not strained at
+    /// their new rest length. Nodes with no links have no strain.
+    pub fn node_strains(&self, out: &mut Vec<f32>) {
+        let positions = self.nodes.current_pos_slice();
+        out.clear();
+        out.resize(positions.len(), 0.0);
+
+        let links = self
+            .links
+            .relation_slice()
+            .iter()
+            .zip(self.links.rest_length_slice());
+        for (&LinkNodes(a, b), &rest) in links {
+            if rest <= 0.0 {
+                continue;
+            }
+            let i_a = unsafe { self.nodes.get_indirect_unchecked(a) } as usize;
+            let i_b = unsafe { self.nodes.get_indirect_unchecked(b) } as usize;
+
+            let strain = (positions[i_a].distance(positions[i_b]) - rest).abs() / rest;
+            out[i_a] = out[i_a].max(strain);
+            out[i_b] = out[i_b].max(strain);
+        }
     }
 
     #[inline]
@@ -134,11 +391,181 @@ impl XpbdSystem {
         lattice_builder: XpbdLatticeBuilder,
     ) -> physics::xpbd::LatticeIds {
         let map = lattice_builder.export(&mut self.nodes, &mut self.links);
+        self.previous_pos
+            .extend_from_slice(&self.nodes.current_pos_slice()[self.previous_pos.len()..]);
+        self.solver.track_links(&map.links, &self.links);
+        self.islands
+            .insert_lattice(&map, &mut self.nodes, &self.links);
 
-        //todo: only recompute basis for new nodes/constraints, don't overwrite
         self.rotor_system
-            .recompute_basis_cache(&self.nodes, &self.links, true);
+            .extend_basis_cache(&self.nodes, &self.links, &map.links);
 
         map
     }
+
+    /// Free the nodes and links of a `lattice` previously imported with
+    /// [`XpbdSystem::import_lattice`].
+    ///
+    /// External nodes of the lattice are left live. Any other link attached
+    /// to one of its own nodes is freed as well. Links
+    /// of the lattice that already broke are skipped; broken links pending
+    /// to be reported are dropped.
+    ///
+    /// The handles in `lattice` must not be used once this returns: they may
+    /// be reused by the next imported lattice.
+    pub fn remove_lattice(&mut self, lattice: &physics::xpbd::LatticeIds) {
+        let lattice_nodes = lattice.nodes.iter().copied().collect::<FxHashSet<_>>();
+        let lattice_links = lattice.links.iter().copied().collect::<FxHashSet<_>>();
+        let removed_nodes = lattice
+            .owned_nodes()
+            .filter(|&node| node != 0 && self.nodes.get_indirect(node).is_some())
+            .collect::<FxHashSet<_>>();
+
+        // links between external nodes belong to the lattice too
+        let removed_links = self
+            .links
+            .handles()
+            .iter()
+            .zip(self.links.relation_slice())
+            .filter(|&(handle, LinkNodes(a, b))| {
+                removed_nodes.contains(a)
+                    || removed_nodes.contains(b)
+                    || (lattice_nodes.contains(a)
+                        && lattice_nodes.contains(b)
+                        && lattice_links.contains(handle))
+            })
+            .map(|(&handle, _)| handle)
+            .collect::<Vec<_>>();
+        if removed_links.is_empty() && removed_nodes.is_empty() {
+            return;
+        }
+        let removed_nodes = removed_nodes.into_iter().collect::<Vec<_>>();
+
+        self.solver.untrack_links(&removed_links);
+        self.pending_breaks
+            .retain(|(handle, _)| !removed_links.contains(handle));
+        self.islands.remove_nodes(&removed_nodes, &mut self.nodes);
+        self.rotor_system.remove_links(&removed_links);
+        self.rotor_system.remove_nodes(&removed_nodes);
+
+        // previous positions must follow the rows moved by freeing
+        let old_slots = self.nodes.slots_map().to_vec();
+        let previous_pos = std::mem::take(&mut self.previous_pos);
+
+        for &link in &removed_links {
+            self.links.free(link);
+        }
+        for &node in &removed_nodes {
+            self.nodes.free(node);
+        }
+
+        self.previous_pos.extend(
+            self.nodes
+                .handles()
+                .iter()
+                .zip(self.nodes.current_pos_slice())
+                .map(|(&handle, &pos)| {
+                    let old_index = old_slots[handle as usize] as usize;
+                    previous_pos.get(old_index).copied().unwrap_or(pos)
+                }),
+        );
+
+        // rotations are parallel to the nodes
+        self.rotor_system
+            .recompute_rotations(&self.nodes, &self.links);
+    }
+}
+
+/// The complete simulation state, including links broken by hand that were
+/// not yet reported.
+///
+/// Node and link handles stay valid across a save and restore.
+impl Snapshot for XpbdSystem {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.nodes);
+        writer.write(&self.links);
+        writer.write(&self.solver);
+        writer.write(&self.rotor_system);
+        writer.write(&self.islands);
+        writer.write(&self.sleep_options);
+        writer.write(&self.fixed_step);
+        writer.write(&self.accumulator);
+        writer.write(&self.alpha);
+        writer.write(&self.previous_pos);
+        writer.write(&self.pending_breaks);
+        writer.write(&self.frame_broken_links);
+        writer.write(&self.frame_broken_relations);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        let system = Self {
+            nodes: reader.read()?,
+            links: reader.read()?,
+            solver: reader.read()?,
+            rotor_system: reader.read()?,
+            islands: reader.read()?,
+            sleep_options: reader.read()?,
+            fixed_step: reader.read()?,
+            accumulator: reader.read()?,
+            alpha: reader.read()?,
+            previous_pos: reader.read()?,
+            pending_breaks: reader.read()?,
+            frame_broken_links: reader.read()?,
+            frame_broken_relations: reader.read()?,
+        };
+
+        // the solver and islands index nodes by the handles links refer to
+        let live = |node: u32| node != 0 && system.nodes.get_indirect(node).is_some();
+        let relations = &system.links.relation_slice()[1..];
+        if !relations.iter().all(|&LinkNodes(a, b)| live(a) && live(b)) {
+            return Err(SnapshotError::Corrupt("link node is not live"));
+        }
+        let islands = system.islands.islands();
+        if !islands
+            .flat_map(|(_, island)| island.nodes())
+            .all(|&node| live(node))
+        {
+            return Err(SnapshotError::Corrupt("island node is not live"));
+        }
+        if system.previous_pos.len() > system.nodes.len() {
+            return Err(SnapshotError::Corrupt("previous positions out of range"));
+        }
+        Ok(system)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use physics::xpbd::{XpbdLinkOptions, XpbdNodeOptions, XpbdOptions};
+
+    /// Let a pendulum swing for `frames` frames of `1 / rate` seconds, and
+    /// return where its bob ends up.
+    fn swing_pendulum(rate: f32, frames: u32) -> glam::Vec3 {
+        let options = XpbdOptions::default().with_gravity(glam::vec3(1.0, -9.81, 0.0));
+        let mut system = XpbdSystem::new(XpbdSolver::new(options));
+
+        let mut builder = XpbdLatticeBuilder::new();
+        builder.node(XpbdNodeOptions::new(glam::Vec3::ZERO, 1.0).with_fixed(true));
+        builder.node(XpbdNodeOptions::new(glam::Vec3::X, 1.0));
+        builder.link(XpbdLinkOptions::new(0.0));
+        let map = system.import_lattice(builder);
+
+        for _ in 0..frames {
+            system.update_seconds(1.0 / rate);
+        }
+
+        let index = system.nodes().get_indirect(map.nodes[1]).unwrap();
+        system.nodes().current_pos_slice()[index as usize]
+    }
+
+    #[test]
+    fn xpbd_system_ignores_frame_rate() {
+        // both run 31 fixed steps, with time to spare
+        let at_60 = swing_pendulum(60.0, 31);
+        let at_144 = swing_pendulum(144.0, 75);
+
+        assert!(at_60.y < -0.1);
+        assert!(at_60.distance(at_144) < 1e-5, "{at_60} != {at_144}");
+    }
 }
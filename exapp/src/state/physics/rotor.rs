@@ -1,17 +1,28 @@
-use ethel::state::data::{Column, ParallelIndexArrayColumn, SparseSlot, column::IterColumn};
-use physics::xpbd::{LinkNodes, LinksRowTable, NodesRowTable};
+use ethel::state::data::Column;
+use physics::{
+    shape_match::{DEFAULT_MATCH_ITERATIONS, ShapeMatch},
+    snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter},
+    xpbd::{LinkNodes, LinksRowTable, NodesRowTable},
+};
+
+/// Lower bound of the compliance used to weight links, so that rigid links
+/// get a finite weight.
+const MIN_WEIGHT_COMPLIANCE: f32 = 1e-9;
 
 #[derive(Debug, Default)]
 pub struct RotorSystem {
     /// Final computed rotations of nodes
     rotations: Vec<glam::Quat>,
 
-    /// Mapping between node handle to internal storage handles
-    node_map: Vec<RotorHandle>,
+    /// Rest direction of each link, from its first node to its second;
+    /// sparse map by link handle, zero if not cached
+    basis: Vec<glam::Vec3>,
+
+    /// Last computed rotation of each node; sparse map by node handle
+    previous: Vec<glam::Quat>,
 
-    //todo: don't nest Vec's
-    relatives: ParallelIndexArrayColumn<Vec<glam::Vec3>>,
-    basis: ParallelIndexArrayColumn<Vec<glam::Vec3>>,
+    // per-frame scratch, parallel to the node contiguous data
+    matches: Vec<ShapeMatch>,
 }
 
 impl RotorSystem {
@@ -22,9 +33,9 @@ impl RotorSystem {
     pub fn with_capacity(capacity: usize) -> Self {
         Self {
             rotations: Vec::with_capacity(capacity),
-            node_map: Vec::with_capacity(capacity),
-            relatives: ParallelIndexArrayColumn::with_capacity(capacity),
-            basis: ParallelIndexArrayColumn::with_capacity(capacity),
+            basis: Vec::with_capacity(capacity),
+            previous: Vec::with_capacity(capacity),
+            matches: Vec::with_capacity(capacity),
         }
     }
 
@@ -45,116 +56,291 @@ impl RotorSystem {
         &self.rotations
     }
 
-    pub fn clear_relatives(&mut self) {
-        self.relatives.iter_mut().for_each(Vec::clear);
-    }
-
-    pub fn recompute_basis_cache(
+    /// Cache the rest direction of the `added` links only, such as the links
+    /// of a freshly imported lattice.
+    ///
+    /// The rest directions of all other links are kept: structures that are
+    /// already simulated keep their rest rotations, even while deformed.
+    /// Links whose nodes coincide have no rest direction, and are not
+    /// cached.
+    pub fn extend_basis_cache(
         &mut self,
         nodes: &NodesRowTable,
         constraints: &LinksRowTable,
-        overwrite: bool,
+        added: &[u32],
     ) {
-        if overwrite {
-            self.basis.slots_map_mut().resize(1, 0);
-            self.basis.free_list_mut().clear();
-            self.basis.handles_mut().fill(0);
-            self.basis.contiguous_mut().iter_mut().for_each(Vec::clear);
+        for &link in added {
+            let index = unsafe { constraints.get_indirect_unchecked(link) };
+            let LinkNodes(node_a, node_b) = constraints.relation_slice()[index as usize];
+
+            let len = link as usize + 1;
+            if self.basis.len() < len {
+                self.basis.resize(len, glam::Vec3::ZERO);
+            }
+            self.basis[link as usize] = Self::direction(nodes, node_a, node_b);
         }
+    }
+
+    /// Forget the rest direction of the `removed` links, such as links that
+    /// broke or were freed.
+    ///
+    /// Their nodes stop following them from the next
+    /// [`RotorSystem::recompute_rotations`] on.
+    pub fn remove_links(&mut self, removed: &[u32]) {
+        for &link in removed {
+            if let Some(basis) = self.basis.get_mut(link as usize) {
+                *basis = glam::Vec3::ZERO;
+            }
+        }
+    }
 
-        for LinkNodes(node_a, node_b) in constraints.relation_view() {
-            let rot_a = self.node_rotors_handle(*node_a).basis;
-            let rot_b = self.node_rotors_handle(*node_b).basis;
+    /// Forget the last rotation of the `removed` nodes, so their handles
+    /// start from the identity if they are reused.
+    pub fn remove_nodes(&mut self, removed: &[u32]) {
+        for &node in removed {
+            if let Some(rotation) = self.previous.get_mut(node as usize) {
+                *rotation = glam::Quat::IDENTITY;
+            }
+        }
+    }
 
-            let i_a = unsafe { nodes.get_indirect_unchecked(*node_a) };
-            let i_b = unsafe { nodes.get_indirect_unchecked(*node_b) };
+    /// Recompute the rotation of every node from the current direction of its
+    /// links, relative to their rest direction.
+    ///
+    /// Each rotation is the least-squares best fit of the rest directions of
+    /// the links of a node onto their current directions, weighted by link
+    /// stiffness; see [`ShapeMatch`]. It is found starting from the last
+    /// rotation of the node, so nodes left with:
+    /// * no links keep their last rotation.
+    /// * a single link keep their last twist around it, and only swing to
+    ///   follow its direction.
+    ///
+    /// Links are paired with their rest direction by handle; links without
+    /// one, such as broken links, are skipped, and so are links whose nodes
+    /// currently coincide.
+    pub fn recompute_rotations(&mut self, nodes: &NodesRowTable, constraints: &LinksRowTable) {
+        let len = nodes.current_pos_slice().len();
+        self.matches.clear();
+        self.matches.resize(len, ShapeMatch::new());
 
-            let pos_a = nodes.current_pos_slice()[i_a as usize];
-            let pos_b = nodes.current_pos_slice()[i_b as usize];
+        let links = constraints
+            .handles()
+            .iter()
+            .zip(constraints.relation_slice())
+            .zip(constraints.compliance_slice());
+        for ((&link, &LinkNodes(node_a, node_b)), &compliance) in links {
+            let Some(&basis) = self
+                .basis
+                .get(link as usize)
+                .filter(|basis| **basis != glam::Vec3::ZERO)
+            else {
+                continue;
+            };
+            let relative = Self::direction(nodes, node_a, node_b);
+            if relative == glam::Vec3::ZERO {
+                continue;
+            }
+            let weight = 1.0 / compliance.max(MIN_WEIGHT_COMPLIANCE);
 
-            let ci_a = unsafe { self.basis.get_indirect_unchecked(rot_a) };
-            let ci_b = unsafe { self.basis.get_indirect_unchecked(rot_b) };
+            // as seen from the second node, both directions are negated
+            for (node, sign) in [(node_a, 1.0), (node_b, -1.0)] {
+                let i = unsafe { nodes.get_indirect_unchecked(node) } as usize;
+                self.matches[i].add(basis * sign, relative * sign, weight);
+            }
+        }
 
-            let basis_a = (pos_b - pos_a).normalize();
-            let basis_b = -basis_a;
+        let max_handle = nodes.handles().iter().max().copied().unwrap_or_default();
+        if self.previous.len() <= max_handle as usize {
+            self.previous
+                .resize(max_handle as usize + 1, glam::Quat::IDENTITY);
+        }
 
-            self.basis.contiguous_mut()[ci_a as usize].push(basis_a);
-            self.basis.contiguous_mut()[ci_b as usize].push(basis_b);
+        self.rotations.clear();
+        for (shape, &handle) in self.matches.iter().zip(nodes.handles()) {
+            let previous = &mut self.previous[handle as usize];
+            *previous = shape.rotation(*previous, DEFAULT_MATCH_ITERATIONS);
+            self.rotations.push(*previous);
         }
     }
 
-    pub fn recompute_relatives(&mut self, nodes: &NodesRowTable, constraints: &LinksRowTable) {
-        self.clear_relatives();
+    fn direction(nodes: &NodesRowTable, node_a: u32, node_b: u32) -> glam::Vec3 {
+        let i_a = unsafe { nodes.get_indirect_unchecked(node_a) };
+        let i_b = unsafe { nodes.get_indirect_unchecked(node_b) };
 
-        for LinkNodes(node_a, node_b) in constraints.relation_view() {
-            let rot_a = self.node_rotors_handle(*node_a).relative;
-            let rot_b = self.node_rotors_handle(*node_b).relative;
+        let pos_a = nodes.current_pos_slice()[i_a as usize];
+        let pos_b = nodes.current_pos_slice()[i_b as usize];
+        // zero for coincident nodes, which have no direction
+        (pos_b - pos_a).normalize_or_zero()
+    }
+}
 
-            let i_a = unsafe { nodes.get_indirect_unchecked(*node_a) };
-            let i_b = unsafe { nodes.get_indirect_unchecked(*node_b) };
+impl Snapshot for RotorSystem {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.rotations);
+        writer.write(&self.basis);
+        writer.write(&self.previous);
+    }
 
-            let pos_a = nodes.current_pos_slice()[i_a as usize];
-            let pos_b = nodes.current_pos_slice()[i_b as usize];
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self {
+            rotations: reader.read()?,
+            basis: reader.read()?,
+            previous: reader.read()?,
+            matches: Vec::new(),
+        })
+    }
+}
 
-            let ci_a = unsafe { self.relatives.get_indirect_unchecked(rot_a) };
-            let ci_b = unsafe { self.relatives.get_indirect_unchecked(rot_b) };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use physics::xpbd::{LatticeIds, XpbdLatticeBuilder, XpbdLinkOptions, XpbdNodeOptions};
 
-            let relative_a = (pos_b - pos_a).normalize();
-            let relative_b = -relative_a;
+    fn assert_rotation_eq(a: glam::Quat, b: glam::Quat) {
+        assert!(a.angle_between(b) < 1e-3, "{a} != {b}");
+    }
 
-            self.relatives.contiguous_mut()[ci_a as usize].push(relative_a);
-            self.relatives.contiguous_mut()[ci_b as usize].push(relative_b);
+    /// Export a node at `center`, linked to a node at each of `ends`, and
+    /// cache the rest direction of its links.
+    fn export_star(
+        rotors: &mut RotorSystem,
+        nodes: &mut NodesRowTable,
+        links: &mut LinksRowTable,
+        center: glam::Vec3,
+        ends: &[glam::Vec3],
+    ) -> LatticeIds {
+        let mut builder = XpbdLatticeBuilder::new();
+        let hub = builder.node(XpbdNodeOptions::new(center, 1.0));
+        for &end in ends {
+            let end = builder.node(XpbdNodeOptions::new(end, 1.0));
+            builder.link_nodes(hub, end, XpbdLinkOptions::new(0.0));
         }
+        let map = builder.export(nodes, links);
+        rotors.extend_basis_cache(nodes, links, &map.links);
+        map
     }
 
-    pub fn recompute_rotations(&mut self, nodes: &NodesRowTable) {
-        self.rotations.clear();
-        for handle in nodes.handles_view() {
-            let rotor = self.node_rotors_handle(*handle);
-            if let Some(basis_id) = self.basis.get_indirect(rotor.basis) {
-                let basis = &self.basis.contiguous()[basis_id as usize];
-
-                // SAFETY: relatives are computed every frame before computing
-                // rotations.
-                let relatives_id = unsafe { self.relatives.get_indirect_unchecked(rotor.relative) };
-                let relatives = &self.relatives.contiguous()[relatives_id as usize];
-
-                let mut q = glam::Quat::default();
-                basis.iter().zip(relatives).for_each(|(&basis, &rel)| {
-                    let mut r = glam::Quat::from_rotation_arc(basis, rel);
-                    // invert sign of quaternion r if rotation is on opposite
-                    // hemisphere
-                    if q.dot(r) < 0.0 {
-                        r = -r;
-                    }
-                    q += r;
-                });
-                self.rotations.push(q);
-            }
-        }
+    fn move_node(nodes: &mut NodesRowTable, node: u32, position: glam::Vec3) {
+        let index = nodes.get_indirect(node).unwrap() as usize;
+        nodes.current_pos_mut_slice()[index] = position;
     }
 
-    /// Get the stable handle for the internal rotors data for `node_id`.
-    pub fn node_rotors_handle(&mut self, node_id: u32) -> RotorHandle {
-        let index = node_id as usize;
+    fn rotation_of(rotors: &RotorSystem, nodes: &NodesRowTable, node: u32) -> glam::Quat {
+        rotors.rotations()[nodes.get_indirect(node).unwrap() as usize]
+    }
 
-        if self.node_map.len() <= index {
-            self.node_map.resize(index + 1, RotorHandle::default());
-        }
-        let map = &mut self.node_map[index];
+    #[test]
+    fn rotor_rest_directions_survive_import() {
+        let (mut rotors, mut nodes, mut links) = Default::default();
+        let first = export_star(
+            &mut rotors,
+            &mut nodes,
+            &mut links,
+            glam::Vec3::ZERO,
+            &[glam::Vec3::X],
+        );
 
-        if map.basis == 0 {
-            map.basis = self.basis.put(Vec::new());
-        }
-        if map.relative == 0 {
-            map.relative = self.relatives.put(Vec::new());
-        }
-        *map
+        // deformed before the next import
+        move_node(&mut nodes, first.nodes[1], glam::Vec3::Y);
+        let second = export_star(
+            &mut rotors,
+            &mut nodes,
+            &mut links,
+            glam::Vec3::Z,
+            &[glam::vec3(1.0, 0.0, 1.0)],
+        );
+        rotors.recompute_rotations(&nodes, &links);
+
+        let turned = glam::Quat::from_rotation_z(std::f32::consts::FRAC_PI_2);
+        assert_rotation_eq(rotation_of(&rotors, &nodes, first.nodes[0]), turned);
+        assert_rotation_eq(rotation_of(&rotors, &nodes, first.nodes[1]), turned);
+        assert_rotation_eq(
+            rotation_of(&rotors, &nodes, second.nodes[0]),
+            glam::Quat::IDENTITY,
+        );
     }
-}
 
-#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct RotorHandle {
-    pub basis: u32,
-    pub relative: u32,
+    #[test]
+    fn rotor_broken_links_are_dropped() {
+        let (mut rotors, mut nodes, mut links) = Default::default();
+        let map = export_star(
+            &mut rotors,
+            &mut nodes,
+            &mut links,
+            glam::Vec3::ZERO,
+            &[glam::Vec3::X, glam::Vec3::Y],
+        );
+
+        // the broken link no longer turns its nodes, while the other holds
+        rotors.remove_links(&map.links[1..]);
+        move_node(&mut nodes, map.nodes[2], glam::Vec3::Z);
+        rotors.recompute_rotations(&nodes, &links);
+
+        let hub = rotation_of(&rotors, &nodes, map.nodes[0]);
+        assert_rotation_eq(hub, glam::Quat::IDENTITY);
+        assert_rotation_eq(
+            rotation_of(&rotors, &nodes, map.nodes[2]),
+            glam::Quat::IDENTITY,
+        );
+    }
+
+    #[test]
+    fn rotor_underdetermined_nodes() {
+        let (mut rotors, mut nodes, mut links) = Default::default();
+        let map = export_star(
+            &mut rotors,
+            &mut nodes,
+            &mut links,
+            glam::Vec3::ZERO,
+            &[glam::Vec3::X, glam::Vec3::Y],
+        );
+        let hub = map.nodes[0];
+
+        let turned = glam::Quat::from_euler(glam::EulerRot::YXZ, 0.7, -0.3, 0.4);
+        move_node(&mut nodes, map.nodes[1], turned * glam::Vec3::X);
+        move_node(&mut nodes, map.nodes[2], turned * glam::Vec3::Y);
+        rotors.recompute_rotations(&nodes, &links);
+        assert_rotation_eq(rotation_of(&rotors, &nodes, hub), turned);
+
+        // a single link keeps the twist around it, and swings to follow it
+        rotors.remove_links(&map.links[1..]);
+        let direction = glam::vec3(0.2, 1.0, -0.5).normalize();
+        move_node(&mut nodes, map.nodes[1], direction);
+        rotors.recompute_rotations(&nodes, &links);
+        let swing = glam::Quat::from_rotation_arc(turned * glam::Vec3::X, direction);
+        assert_rotation_eq(rotation_of(&rotors, &nodes, hub), swing * turned);
+
+        // no links keep the last rotation
+        rotors.remove_links(&map.links);
+        move_node(&mut nodes, map.nodes[1], glam::Vec3::NEG_Z);
+        rotors.recompute_rotations(&nodes, &links);
+        assert_rotation_eq(rotation_of(&rotors, &nodes, hub), swing * turned);
+    }
+
+    #[test]
+    fn rotor_coincident_nodes_are_skipped() {
+        let (mut rotors, mut nodes, mut links) = Default::default();
+        let map = export_star(
+            &mut rotors,
+            &mut nodes,
+            &mut links,
+            glam::Vec3::ZERO,
+            &[glam::Vec3::X, glam::Vec3::ZERO],
+        );
+
+        // never cached at rest, and skipped while collapsed
+        move_node(&mut nodes, map.nodes[1], glam::Vec3::ZERO);
+        rotors.recompute_rotations(&nodes, &links);
+        for &node in &map.nodes {
+            assert_rotation_eq(rotation_of(&rotors, &nodes, node), glam::Quat::IDENTITY);
+        }
+
+        // and still match once they part again
+        move_node(&mut nodes, map.nodes[1], glam::Vec3::Y);
+        rotors.recompute_rotations(&nodes, &links);
+        assert_rotation_eq(
+            rotation_of(&rotors, &nodes, map.nodes[1]),
+            glam::Quat::from_rotation_z(std::f32::consts::FRAC_PI_2),
+        );
+    }
 }
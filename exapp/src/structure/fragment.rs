@@ -2,9 +2,17 @@ use ethel::state::data::{
     Column,
     hash::{Cell, FxSpatialHash, SpatialResolution},
 };
-use physics::xpbd::{LinkNodes, LinksRowTable};
+use janus::context::DeltaTime;
+use physics::{
+    RigidBody, integrate_bare_body_seconds,
+    material::Surface,
+    snapshot::{HandleMaps, Snapshot, SnapshotError, SnapshotReader, SnapshotWriter},
+    xpbd::LinkNodes,
+};
 use rustc_hash::FxHashSet;
 
+use crate::state::physics::XpbdSystem;
+
 #[repr(u32)]
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum FragmentState {
@@ -40,20 +48,448 @@ ethel::table_spec! {
         health: f32; // also acts as mass in Debris state
 
         position: glam::Vec3;
+        rotation: glam::Quat;
         velocity: glam::Vec3;
         forces: glam::Vec3;
+
+        // seconds since released as debris
+        age: f32;
+        // seconds spent as debris under the settle speed
+        rest_time: f32;
+        // the chunk of debris this moves with; 0 if none
+        chunk: u32;
+    }
+}
+
+/// Health of newly generated fragments.
+///
+/// Mirrored by `fragment.vsh`, which shades fragments by their lost health.
+pub const DEFAULT_FRAGMENT_HEALTH: f32 = 100.0;
+
+pub const DEFAULT_DEBRIS_GRAVITY: glam::Vec3 = glam::vec3(0.0, -9.81, 0.0);
+/// Half the extent of the fragment cube drawn by `fragment.vsh`.
+pub const DEFAULT_DEBRIS_RADIUS: f32 = 0.375;
+pub const DEFAULT_SETTLE_SPEED: f32 = 0.1;
+pub const DEFAULT_SETTLE_TIME: f32 = 1.0;
+pub const DEFAULT_SPLIT_SPEED: f32 = 12.0;
+
+/// Lower bound of the mass of a debris fragment, so that fragments with no
+/// health left still fall.
+const MIN_DEBRIS_MASS: f32 = 1e-3;
+
+/// Height over the ground within which a fragment of a chunk touches it.
+const CONTACT_SLOP: f32 = 1e-3;
+
+/// Motion of [`FragmentState::Debris`] fragments.
+///
+/// Debris falls by `gravity` onto a ground plane at `ground_level`, which it
+/// touches at `radius` from its position. On contact, `surface` decides how
+/// much it bounces and slides.
+///
+/// Debris settles into [`FragmentState::InactiveDebris`] once its speed stays
+/// under `settle_speed` for `settle_time` seconds. Chunks of debris split in
+/// two when they hit the ground faster than `split_speed`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DebrisOptions {
+    pub gravity: glam::Vec3,
+    pub ground_level: f32,
+    pub radius: f32,
+    pub surface: Surface,
+    pub settle_speed: f32,
+    pub settle_time: f32,
+    pub split_speed: f32,
+}
+
+impl DebrisOptions {
+    pub const fn new(
+        gravity: glam::Vec3,
+        ground_level: f32,
+        radius: f32,
+        surface: Surface,
+        settle_speed: f32,
+        settle_time: f32,
+        split_speed: f32,
+    ) -> Self {
+        Self {
+            gravity,
+            ground_level,
+            radius,
+            surface,
+            settle_speed,
+            settle_time,
+            split_speed,
+        }
+    }
+
+    pub const fn with_gravity(self, gravity: glam::Vec3) -> Self {
+        Self {
+            gravity,
+            ground_level: self.ground_level,
+            radius: self.radius,
+            surface: self.surface,
+            settle_speed: self.settle_speed,
+            settle_time: self.settle_time,
+            split_speed: self.split_speed,
+        }
+    }
+
+    pub const fn with_ground_level(self, ground_level: f32) -> Self {
+        Self {
+            ground_level,
+            gravity: self.gravity,
+            radius: self.radius,
+            surface: self.surface,
+            settle_speed: self.settle_speed,
+            settle_time: self.settle_time,
+            split_speed: self.split_speed,
+        }
+    }
+
+    pub const fn with_radius(self, radius: f32) -> Self {
+        Self {
+            radius,
+            gravity: self.gravity,
+            ground_level: self.ground_level,
+            surface: self.surface,
+            settle_speed: self.settle_speed,
+            settle_time: self.settle_time,
+            split_speed: self.split_speed,
+        }
+    }
+
+    pub const fn with_surface(self, surface: Surface) -> Self {
+        Self {
+            surface,
+            gravity: self.gravity,
+            ground_level: self.ground_level,
+            radius: self.radius,
+            settle_speed: self.settle_speed,
+            settle_time: self.settle_time,
+            split_speed: self.split_speed,
+        }
+    }
+
+    pub const fn with_settle_speed(self, settle_speed: f32) -> Self {
+        Self {
+            settle_speed,
+            gravity: self.gravity,
+            ground_level: self.ground_level,
+            radius: self.radius,
+            surface: self.surface,
+            settle_time: self.settle_time,
+            split_speed: self.split_speed,
+        }
+    }
+
+    pub const fn with_settle_time(self, settle_time: f32) -> Self {
+        Self {
+            settle_time,
+            gravity: self.gravity,
+            ground_level: self.ground_level,
+            radius: self.radius,
+            surface: self.surface,
+            settle_speed: self.settle_speed,
+            split_speed: self.split_speed,
+        }
+    }
+
+    pub const fn with_split_speed(self, split_speed: f32) -> Self {
+        Self {
+            split_speed,
+            gravity: self.gravity,
+            ground_level: self.ground_level,
+            radius: self.radius,
+            surface: self.surface,
+            settle_speed: self.settle_speed,
+            settle_time: self.settle_time,
+        }
+    }
+}
+
+impl Default for DebrisOptions {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_DEBRIS_GRAVITY,
+            0.0,
+            DEFAULT_DEBRIS_RADIUS,
+            Surface::DEFAULT,
+            DEFAULT_SETTLE_SPEED,
+            DEFAULT_SETTLE_TIME,
+            DEFAULT_SPLIT_SPEED,
+        )
+    }
+}
+
+impl Snapshot for DebrisOptions {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.gravity);
+        writer.write(&self.ground_level);
+        writer.write(&self.radius);
+        writer.write(&self.surface);
+        writer.write(&self.settle_speed);
+        writer.write(&self.settle_time);
+        writer.write(&self.split_speed);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self::new(
+            reader.read()?,
+            reader.read()?,
+            reader.read()?,
+            reader.read()?,
+            reader.read()?,
+            reader.read()?,
+            reader.read()?,
+        ))
+    }
+}
+
+pub const DEFAULT_DESPAWN_COUNT: usize = 2048;
+pub const DEFAULT_DESPAWN_AGE: f32 = 60.0;
+
+/// Limits past which [`FragmentState::InactiveDebris`] fragments are removed
+/// by [`FragmentSystem::despawn_debris`]; `None` disables a limit.
+///
+/// * `max_count`: the most inactive debris kept, the oldest is removed first.
+/// * `max_age`: the most seconds since a fragment was released as debris.
+/// * `max_distance`: the furthest inactive debris is kept from the camera.
+///
+/// Debris still in movement is never removed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DespawnPolicy {
+    pub max_count: Option<usize>,
+    pub max_age: Option<f32>,
+    pub max_distance: Option<f32>,
+}
+
+impl DespawnPolicy {
+    /// A policy that keeps all debris.
+    pub const NEVER: Self = Self::new(None, None, None);
+
+    pub const fn new(
+        max_count: Option<usize>,
+        max_age: Option<f32>,
+        max_distance: Option<f32>,
+    ) -> Self {
+        Self {
+            max_count,
+            max_age,
+            max_distance,
+        }
+    }
+
+    pub const fn with_max_count(self, max_count: Option<usize>) -> Self {
+        Self {
+            max_count,
+            max_age: self.max_age,
+            max_distance: self.max_distance,
+        }
+    }
+
+    pub const fn with_max_age(self, max_age: Option<f32>) -> Self {
+        Self {
+            max_age,
+            max_count: self.max_count,
+            max_distance: self.max_distance,
+        }
+    }
+
+    pub const fn with_max_distance(self, max_distance: Option<f32>) -> Self {
+        Self {
+            max_distance,
+            max_count: self.max_count,
+            max_age: self.max_age,
+        }
+    }
+}
+
+impl Default for DespawnPolicy {
+    fn default() -> Self {
+        Self::new(Some(DEFAULT_DESPAWN_COUNT), Some(DEFAULT_DESPAWN_AGE), None)
+    }
+}
+
+impl Snapshot for DespawnPolicy {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.max_count.map(|count| count as u64));
+        writer.write(&self.max_age);
+        writer.write(&self.max_distance);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self::new(
+            reader.read::<Option<u64>>()?.map(|count| count as usize),
+            reader.read()?,
+            reader.read()?,
+        ))
+    }
+}
+
+/// A fragment of a [`Chunk`], placed in the body space of the chunk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChunkMember {
+    pub fragment: u32,
+    pub offset: glam::Vec3,
+    pub rotation: glam::Quat,
+}
+
+impl Snapshot for ChunkMember {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.fragment);
+        writer.write(&self.offset);
+        writer.write(&self.rotation);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self {
+            fragment: reader.read()?,
+            offset: reader.read()?,
+            rotation: reader.read()?,
+        })
+    }
+}
+
+/// Debris fragments moving as one rigid body.
+///
+/// Chunks are formed by the fragments released from the same island in the
+/// same frame, see [`FragmentSystem::release_debris`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Chunk {
+    body: Option<RigidBody>,
+    members: Vec<ChunkMember>,
+    rest_time: f32,
+}
+
+impl Chunk {
+    /// The body of the chunk.
+    ///
+    /// # Panics
+    /// Will panic if the chunk is not live.
+    #[inline]
+    pub fn body(&self) -> &RigidBody {
+        self.body.as_ref().expect("live chunk")
+    }
+
+    #[inline]
+    pub fn members(&self) -> &[ChunkMember] {
+        &self.members
+    }
+
+    #[inline]
+    fn is_alive(&self) -> bool {
+        self.body.is_some()
+    }
+}
+
+impl Snapshot for Chunk {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.body);
+        writer.write(&self.members);
+        writer.write(&self.rest_time);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self {
+            body: reader.read()?,
+            members: reader.read()?,
+            rest_time: reader.read()?,
+        })
+    }
+}
+
+pub const DEFAULT_DETACH_THRESHOLD: f32 = 0.5;
+
+pub const DEFAULT_STRAIN_THRESHOLD: f32 = 0.02;
+pub const DEFAULT_STRAIN_DAMAGE: f32 = 2_000.0;
+pub const DEFAULT_IMPACT_THRESHOLD: f32 = 4.0;
+pub const DEFAULT_IMPACT_DAMAGE: f32 = 5.0;
+
+/// How fragments lose health.
+///
+/// * Attached fragments lose `strain_damage` health per second for every
+///   unit of strain over `strain_threshold` of the links of their parent
+///   nodes, by the influence of each parent.
+/// * Debris loses `impact_damage` health for every unit of speed over
+///   `impact_threshold` it hits the ground at.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DamageOptions {
+    pub strain_threshold: f32,
+    pub strain_damage: f32,
+    pub impact_threshold: f32,
+    pub impact_damage: f32,
+}
+
+impl DamageOptions {
+    /// Options under which fragments only lose health by explicit damage.
+    pub const NONE: Self = Self::new(f32::INFINITY, 0.0, f32::INFINITY, 0.0);
+
+    pub const fn new(
+        strain_threshold: f32,
+        strain_damage: f32,
+        impact_threshold: f32,
+        impact_damage: f32,
+    ) -> Self {
+        Self {
+            strain_threshold,
+            strain_damage,
+            impact_threshold,
+            impact_damage,
+        }
+    }
+
+    /// The damage of a hit at speed `impact`.
+    #[inline]
+    pub fn impact(&self, impact: f32) -> f32 {
+        (impact - self.impact_threshold).max(0.0) * self.impact_damage
+    }
+}
+
+impl Default for DamageOptions {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_STRAIN_THRESHOLD,
+            DEFAULT_STRAIN_DAMAGE,
+            DEFAULT_IMPACT_THRESHOLD,
+            DEFAULT_IMPACT_DAMAGE,
+        )
+    }
+}
+
+impl Snapshot for DamageOptions {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.strain_threshold);
+        writer.write(&self.strain_damage);
+        writer.write(&self.impact_threshold);
+        writer.write(&self.impact_damage);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self::new(
+            reader.read()?,
+            reader.read()?,
+            reader.read()?,
+            reader.read()?,
+        ))
     }
 }
 
 #[derive(Debug)]
 pub struct FragmentSystem {
     fragments: FragmentsRowTable,
+    debris_options: DebrisOptions,
+    despawn_policy: DespawnPolicy,
+    damage_options: DamageOptions,
 
     // sparse map of node ID to sequence of fragment IDs
     node_map: Vec<Vec<u32>>,
 
-    // alltime accumulated set of disabled node IDs; avoids dedup op
-    disabled_nodes: FxHashSet<u32>,
+    // chunk 0 is degenerate
+    chunks: Vec<Chunk>,
+    free_chunks: Vec<u32>,
+
+    // sparse map of node ID to the amount of its links broken so far
+    broken_links: Vec<u32>,
+    detach_threshold: f32,
 
     // alltime accumulated set of disable fragment IDs; avoids dedup op
     // these are the fragments' indirect indices (stable)
@@ -61,6 +497,9 @@ pub struct FragmentSystem {
     // per-frame list of disabled fragment IDs
     // these are the fragments' direct indices (unstable)
     disabled_frags_frame: Vec<u32>,
+
+    // per-frame scratch, parallel to the node contiguous data
+    node_strains: Vec<f32>,
 }
 
 impl Default for FragmentSystem {
@@ -73,12 +512,19 @@ impl FragmentSystem {
     pub fn new() -> Self {
         Self {
             fragments: FragmentsRowTable::new(),
+            debris_options: DebrisOptions::default(),
+            despawn_policy: DespawnPolicy::default(),
+            damage_options: DamageOptions::default(),
             // account for degenerate
             node_map: vec![Vec::new()],
+            chunks: vec![Chunk::default()],
+            free_chunks: Vec::new(),
 
-            disabled_nodes: FxHashSet::default(),
+            broken_links: Vec::new(),
+            detach_threshold: DEFAULT_DETACH_THRESHOLD,
             disabled_frags_alltime: FxHashSet::default(),
             disabled_frags_frame: Vec::new(),
+            node_strains: Vec::new(),
         }
     }
 
@@ -89,11 +535,18 @@ impl FragmentSystem {
 
         Self {
             fragments: FragmentsRowTable::with_capacity(capacity),
+            debris_options: DebrisOptions::default(),
+            despawn_policy: DespawnPolicy::default(),
+            damage_options: DamageOptions::default(),
             node_map,
+            chunks: vec![Chunk::default()],
+            free_chunks: Vec::new(),
 
-            disabled_nodes: FxHashSet::default(),
+            broken_links: Vec::new(),
+            detach_threshold: DEFAULT_DETACH_THRESHOLD,
             disabled_frags_alltime: FxHashSet::default(),
             disabled_frags_frame: Vec::new(),
+            node_strains: Vec::new(),
         }
     }
 
@@ -124,43 +577,200 @@ impl FragmentSystem {
         &mut self.fragments
     }
 
+    #[inline]
+    pub fn debris_options(&self) -> &DebrisOptions {
+        &self.debris_options
+    }
+
+    #[inline]
+    pub fn set_debris_options(&mut self, options: DebrisOptions) {
+        self.debris_options = options;
+    }
+
+    #[inline]
+    pub fn despawn_policy(&self) -> &DespawnPolicy {
+        &self.despawn_policy
+    }
+
+    #[inline]
+    pub fn set_despawn_policy(&mut self, policy: DespawnPolicy) {
+        self.despawn_policy = policy;
+    }
+
+    /// The fraction of its influence weight a fragment must be held by to
+    /// stay attached; see [`FragmentSystem::handle_constraint_break`].
+    #[inline]
+    pub fn detach_threshold(&self) -> f32 {
+        self.detach_threshold
+    }
+
+    #[inline]
+    pub fn set_detach_threshold(&mut self, threshold: f32) {
+        self.detach_threshold = threshold;
+    }
+
+    #[inline]
+    pub fn damage_options(&self) -> &DamageOptions {
+        &self.damage_options
+    }
+
+    #[inline]
+    pub fn set_damage_options(&mut self, options: DamageOptions) {
+        self.damage_options = options;
+    }
+
+    /// Remove all fragments.
     pub fn reset(&mut self) {
-        self.disabled_nodes.clear();
+        self.fragments = FragmentsRowTable::new();
+        // account for degenerate
         self.node_map.clear();
+        self.node_map.push(Vec::new());
+        self.chunks.clear();
+        self.chunks.push(Chunk::default());
+        self.free_chunks.clear();
+
+        self.broken_links.clear();
+        self.disabled_frags_alltime.clear();
+        self.disabled_frags_frame.clear();
+    }
+
+    /// Free the fragments attached to any of the `removed` nodes.
+    ///
+    /// The nodes are forgotten, so their handles can be reused by newly
+    /// generated fragments. Freeing moves rows of the fragments table: any
+    /// data parallel to it must follow its new order.
+    pub fn remove_nodes(&mut self, removed: &[u32]) {
+        let mut fragments = Vec::new();
+        for &node in removed {
+            if let Some(frags) = self.node_map.get_mut(node as usize) {
+                fragments.append(frags);
+            }
+            if let Some(broken) = self.broken_links.get_mut(node as usize) {
+                *broken = 0;
+            }
+        }
+        self.free_fragments(fragments);
     }
 
-    pub fn handle_constraint_break(&mut self, broken_ids: &[u32], constraints: &LinksRowTable) {
+    /// Free the fragments `frag_ids`, detaching them from their parents.
+    fn free_fragments(&mut self, mut frag_ids: Vec<u32>) {
+        frag_ids.sort_unstable();
+        frag_ids.dedup();
+
+        let mut chunks = Vec::new();
+        for frag_id in frag_ids {
+            let Some(index) = self.fragments.get_indirect(frag_id) else {
+                continue;
+            };
+
+            // a fragment is listed under each of its parents
+            for parent in self.fragments.parents_slice()[index as usize] {
+                if let Some(frags) = self.node_map.get_mut(parent as usize) {
+                    frags.retain(|&id| id != frag_id);
+                }
+            }
+
+            let chunk = self.fragments.chunk_slice()[index as usize];
+            if chunk != 0 {
+                self.chunks[chunk as usize]
+                    .members
+                    .retain(|member| member.fragment != frag_id);
+                chunks.push(chunk);
+            }
+            self.fragments.free(frag_id);
+            self.disabled_frags_alltime.remove(&frag_id);
+        }
         self.disabled_frags_frame.clear();
-        {
-            let f_handles = self.fragments.handles();
-            let relations = constraints.relation_slice();
-
-            for broken in broken_ids {
-                let index = unsafe { constraints.get_indirect_unchecked(*broken) };
-                let LinkNodes(a, b) = *unsafe { relations.get_unchecked(index as usize) };
-
-                if self.disabled_nodes.insert(a) {
-                    for &frag_id in &self.node_map[a as usize] {
-                        if frag_id == 0 {
-                            continue;
-                        }
-                        if self.disabled_frags_alltime.insert(frag_id) {
-                            let index = *unsafe { f_handles.get_unchecked(frag_id as usize) };
-                            self.disabled_frags_frame.push(index);
-                        }
-                    }
+
+        chunks.sort_unstable();
+        chunks.dedup();
+        for chunk in chunks {
+            self.rebuild_chunk(chunk);
+        }
+    }
+
+    /// Weaken the nodes of the links broken during the last
+    /// [`XpbdSystem::update`], and disable the fragments they no longer hold.
+    ///
+    /// A parent node holds a fragment by the fraction of its links still
+    /// intact, out of the links it ever had. Fragments held by less than the
+    /// detach threshold of their influence weight are disabled. The others
+    /// move the weight of the parents with no intact links left onto the
+    /// remaining parents, staying in place.
+    pub fn handle_constraint_break(&mut self, xpbd: &XpbdSystem) {
+        self.disabled_frags_frame.clear();
+
+        let mut touched = Vec::new();
+        for &LinkNodes(a, b) in xpbd.frame_broken_relations() {
+            for node in [a, b] {
+                let len = node as usize + 1;
+                if self.broken_links.len() < len {
+                    self.broken_links.resize(len, 0);
+                }
+                self.broken_links[node as usize] += 1;
+
+                if let Some(frags) = self.node_map.get(node as usize) {
+                    touched.extend_from_slice(frags);
+                }
+            }
+        }
+        touched.sort_unstable();
+        touched.dedup();
+
+        let islands = xpbd.islands();
+        let hold = |node: u32| {
+            let intact = islands.link_count(node) as f32;
+            let broken = self.broken_links.get(node as usize).copied().unwrap_or(0) as f32;
+            if intact + broken > 0.0 {
+                intact / (intact + broken)
+            } else {
+                1.0
+            }
+        };
+
+        for frag_id in touched {
+            if frag_id == 0 || self.disabled_frags_alltime.contains(&frag_id) {
+                continue;
+            }
+            let i = unsafe { self.fragments.get_indirect_unchecked(frag_id) } as usize;
+            let parents = self.fragments.parents_slice()[i];
+            let weights = self.fragments.influence_slice()[i];
+
+            let mut total = 0.0;
+            let mut held = 0.0;
+            let mut surviving = 0.0;
+            for (&parent, &weight) in parents.iter().zip(&weights) {
+                let strength = hold(parent);
+                total += weight;
+                held += weight * strength;
+                if strength > 0.0 {
+                    surviving += weight;
                 }
-                if self.disabled_nodes.insert(b) {
-                    for &frag_id in &self.node_map[b as usize] {
-                        if frag_id == 0 {
-                            continue;
-                        }
-                        if self.disabled_frags_alltime.insert(frag_id) {
-                            let index = *unsafe { f_handles.get_unchecked(frag_id as usize) };
-                            self.disabled_frags_frame.push(index);
-                        }
+            }
+
+            if surviving <= 0.0 || held < total * self.detach_threshold {
+                self.disabled_frags_alltime.insert(frag_id);
+                self.disabled_frags_frame.push(i as u32);
+            } else if surviving < total {
+                let mut renormalised = weights;
+                for (&parent, weight) in parents.iter().zip(&mut renormalised) {
+                    *weight = if hold(parent) > 0.0 {
+                        *weight * total / surviving
+                    } else {
+                        0.0
+                    };
+                }
+
+                // the rest offset follows, so the fragment does not jump
+                let nodes = xpbd.nodes();
+                let mut shift = glam::Vec3::ZERO;
+                for ((&parent, &old), &new) in parents.iter().zip(&weights).zip(&renormalised) {
+                    if let Some(index) = nodes.get_indirect(parent) {
+                        shift += nodes.current_pos_slice()[index as usize] * (old - new);
                     }
                 }
+                self.fragments.influence_mut_slice()[i] = renormalised;
+                self.fragments.rest_offset_mut_slice()[i] += shift;
             }
         }
 
@@ -183,6 +793,644 @@ impl FragmentSystem {
         &self.disabled_frags_frame
     }
 
+    /// Take `amount` of health from the fragment `frag_id`.
+    ///
+    /// Health does not go under zero. Attached fragments with no health left
+    /// detach on the next [`FragmentSystem::update_damage`].
+    pub fn damage(&mut self, frag_id: u32, amount: f32) {
+        if let Some(index) = self.fragments.get_indirect(frag_id) {
+            let health = &mut self.fragments.health_mut_slice()[index as usize];
+            *health = (*health - amount).max(0.0);
+        }
+    }
+
+    /// Take up to `amount` of health from every fragment within `radius` of
+    /// `center`, fading out linearly with distance; see
+    /// [`FragmentSystem::damage`].
+    ///
+    /// This evaluates the [`FragmentSystem::world_transforms`] of attached
+    /// fragments.
+    pub fn damage_area(&mut self, xpbd: &XpbdSystem, center: glam::Vec3, radius: f32, amount: f32) {
+        self.world_transforms(xpbd);
+
+        let FragmentsRowTable {
+            health, position, ..
+        } = &mut self.fragments;
+        // skip degenerate
+        for (health, position) in health.iter_mut().zip(&*position).skip(1) {
+            let distance = position.distance(center);
+            if distance < radius {
+                *health = (*health - amount * (1.0 - distance / radius)).max(0.0);
+            }
+        }
+    }
+
+    /// Damage attached fragments by the strain of their parent nodes over
+    /// `delta`, see [`DamageOptions`], then detach the attached fragments
+    /// with no health left.
+    ///
+    /// Detached fragments are reported along the fragments of broken links:
+    /// call this after [`FragmentSystem::handle_constraint_break`], and
+    /// before [`FragmentSystem::release_debris`].
+    #[inline]
+    pub fn update_damage(&mut self, xpbd: &XpbdSystem, delta: DeltaTime) {
+        self.update_damage_seconds(xpbd, delta.as_f32());
+    }
+
+    /// Damage attached fragments by the strain of their parent nodes over
+    /// `seconds`.
+    ///
+    /// See [`FragmentSystem::update_damage`].
+    pub fn update_damage_seconds(&mut self, xpbd: &XpbdSystem, seconds: f32) {
+        let options = self.damage_options;
+        let t = seconds;
+
+        xpbd.node_strains(&mut self.node_strains);
+        let nodes = xpbd.nodes();
+
+        let first = self.disabled_frags_frame.len();
+        let FragmentsRowTable {
+            parents,
+            influence,
+            state,
+            health,
+            ..
+        } = &mut self.fragments;
+
+        // skip degenerate
+        for i in 1..state.len() {
+            if state[i] != FragmentState::Attached {
+                continue;
+            }
+
+            let mut strain = 0.0;
+            for (&parent, &weight) in parents[i].iter().zip(&influence[i]) {
+                if let Some(index) = nodes.get_indirect(parent) {
+                    let excess = self.node_strains[index as usize] - options.strain_threshold;
+                    strain += excess.max(0.0) * weight;
+                }
+            }
+            health[i] = (health[i] - strain * options.strain_damage * t).max(0.0);
+
+            if health[i] <= 0.0 {
+                state[i] = FragmentState::Debris;
+                self.disabled_frags_frame.push(i as u32);
+            }
+        }
+
+        let handles = self.fragments.handles();
+        for &i in &self.disabled_frags_frame[first..] {
+            self.disabled_frags_alltime.insert(handles[i as usize]);
+        }
+    }
+
+    /// Launch the fragments disabled in the last frame as debris.
+    ///
+    /// Each fragment starts from its skinned world transform, see
+    /// [`FragmentSystem::world_transforms`], moving at the blended velocity of
+    /// its parent nodes. Call this after
+    /// [`FragmentSystem::handle_constraint_break`], while the parent nodes
+    /// are still live.
+    ///
+    /// Fragments released from the same island form a [`Chunk`], and keep
+    /// the momentum of their parent nodes as a whole.
+    pub fn release_debris(&mut self, xpbd: &XpbdSystem) {
+        let nodes = xpbd.nodes();
+        let mut islands = Vec::with_capacity(self.disabled_frags_frame.len());
+        {
+            let FragmentsRowTable {
+                parents,
+                influence,
+                rest_offset,
+                position,
+                rotation,
+                velocity,
+                forces,
+                age,
+                rest_time,
+                chunk,
+                ..
+            } = &mut self.fragments;
+
+            for &i in &self.disabled_frags_frame {
+                let i = i as usize;
+                (position[i], rotation[i]) =
+                    Self::skin(xpbd, parents[i], influence[i], rest_offset[i]);
+
+                velocity[i] = glam::Vec3::ZERO;
+                for (&parent, &weight) in parents[i].iter().zip(&influence[i]) {
+                    if let Some(index) = nodes.get_indirect(parent) {
+                        velocity[i] += nodes.velocity_slice()[index as usize] * weight;
+                    }
+                }
+                forces[i] = glam::Vec3::ZERO;
+                age[i] = 0.0;
+                rest_time[i] = 0.0;
+                chunk[i] = 0;
+
+                let island = parents[i]
+                    .iter()
+                    .zip(&influence[i])
+                    .filter(|&(_, &weight)| weight > 0.0)
+                    .find_map(|(&parent, _)| xpbd.islands().island_of(parent));
+                if let Some(island) = island {
+                    islands.push((island, i as u32));
+                }
+            }
+        }
+
+        islands.sort_unstable();
+        for group in islands.chunk_by(|a, b| a.0 == b.0) {
+            if group.len() > 1 {
+                let indices = group.iter().map(|&(_, i)| i).collect::<Vec<_>>();
+                self.create_chunk(&indices);
+            }
+        }
+    }
+
+    /// Get the chunk `id`.
+    ///
+    /// Returns `None` if `id` does not refer to a live chunk.
+    pub fn chunk(&self, id: u32) -> Option<&Chunk> {
+        self.chunks
+            .get(id as usize)
+            .filter(|chunk| chunk.is_alive())
+    }
+
+    /// Join the debris fragments at the direct `indices` into a new chunk,
+    /// moving with their momentum.
+    ///
+    /// # Returns
+    /// The ID of the chunk, or `0` if the fragments have no mass.
+    fn create_chunk(&mut self, indices: &[u32]) -> u32 {
+        let table = &self.fragments;
+        let cubes = indices
+            .iter()
+            .map(|&i| {
+                let i = i as usize;
+                (
+                    table.position_slice()[i],
+                    table.health_slice()[i].max(MIN_DEBRIS_MASS),
+                )
+            })
+            .collect::<Vec<_>>();
+        let Some(mut body) = RigidBody::from_cubes(&cubes, self.debris_options.radius) else {
+            return 0;
+        };
+
+        let mut momentum = glam::Vec3::ZERO;
+        let mut angular_momentum = glam::Vec3::ZERO;
+        for (&i, &(position, mass)) in indices.iter().zip(&cubes) {
+            let velocity = table.velocity_slice()[i as usize];
+            momentum += velocity * mass;
+            angular_momentum += (position - body.position).cross(velocity) * mass;
+        }
+        body.velocity = momentum * body.inv_mass;
+        body.angular_velocity = body.world_inv_inertia() * angular_momentum;
+
+        let members = indices
+            .iter()
+            .map(|&i| ChunkMember {
+                fragment: table.handles()[i as usize],
+                offset: body.to_local(table.position_slice()[i as usize]),
+                rotation: body.orientation.inverse() * table.rotation_slice()[i as usize],
+            })
+            .collect();
+        let chunk = Chunk {
+            body: Some(body),
+            members,
+            rest_time: 0.0,
+        };
+
+        let id = match self.free_chunks.pop() {
+            Some(id) => {
+                self.chunks[id as usize] = chunk;
+                id
+            }
+            None => {
+                self.chunks.push(chunk);
+                self.chunks.len() as u32 - 1
+            }
+        };
+        for &i in indices {
+            self.fragments.chunk_mut_slice()[i as usize] = id;
+        }
+        id
+    }
+
+    /// Rebuild the body of the chunk `id` from its members, once members
+    /// were removed or lost health.
+    ///
+    /// The chunk keeps moving as it did: the new body takes the velocity the
+    /// old one had at its new centre of mass, and keeps its spin. A chunk
+    /// left with a single fragment is freed.
+    fn rebuild_chunk(&mut self, id: u32) {
+        let chunk = &self.chunks[id as usize];
+        let Some(old) = chunk.body else {
+            return;
+        };
+        let cubes = chunk
+            .members
+            .iter()
+            .map(|member| {
+                let i = unsafe { self.fragments.get_indirect_unchecked(member.fragment) };
+                (
+                    old.to_world(member.offset),
+                    self.fragments.health_slice()[i as usize].max(MIN_DEBRIS_MASS),
+                )
+            })
+            .collect::<Vec<_>>();
+        // a chunk of a single fragment is plain debris
+        if cubes.len() < 2 {
+            self.free_chunk(id);
+            return;
+        }
+        let Some(mut body) = RigidBody::from_cubes(&cubes, self.debris_options.radius) else {
+            self.free_chunk(id);
+            return;
+        };
+        body.velocity = old.velocity_at(body.position);
+        body.angular_velocity = old.angular_velocity;
+
+        let chunk = &mut self.chunks[id as usize];
+        for (member, &(position, _)) in chunk.members.iter_mut().zip(&cubes) {
+            member.offset = body.to_local(position);
+            member.rotation = body.orientation.inverse() * old.orientation * member.rotation;
+        }
+        chunk.body = Some(body);
+    }
+
+    /// Free the chunk `id`, leaving its remaining members as independent
+    /// debris.
+    fn free_chunk(&mut self, id: u32) {
+        let chunk = std::mem::take(&mut self.chunks[id as usize]);
+        for member in chunk.members {
+            if let Some(index) = self.fragments.get_indirect(member.fragment) {
+                self.fragments.chunk_mut_slice()[index as usize] = 0;
+            }
+        }
+        self.free_chunks.push(id);
+    }
+
+    /// Apply `force` to the debris fragment `frag_id`, for the next
+    /// [`FragmentSystem::integrate_debris`].
+    ///
+    /// Fragments in any other state ignore it.
+    pub fn apply_debris_force(&mut self, frag_id: u32, force: glam::Vec3) {
+        let Some(index) = self.fragments.get_indirect(frag_id) else {
+            return;
+        };
+        if self.fragments.state_slice()[index as usize] == FragmentState::Debris {
+            self.fragments.forces_mut_slice()[index as usize] += force;
+        }
+    }
+
+    /// Advance every [`FragmentState::Debris`] fragment by `seconds`, under
+    /// gravity and the forces applied since the last call, colliding with
+    /// the ground; see [`DebrisOptions`].
+    ///
+    /// Call this once per fixed step taken by [`XpbdSystem::update`], so
+    /// that debris moves at the rate of the lattice rather than of frames.
+    ///
+    /// The health of a debris fragment is its mass. Independent debris does
+    /// not spin: it keeps the rotation it was released with, while chunks
+    /// tumble as rigid bodies. Debris that stayed slow for long enough
+    /// settles into [`FragmentState::InactiveDebris`], and stops moving.
+    ///
+    /// Hitting the ground damages debris; see [`DamageOptions`].
+    pub fn integrate_debris(&mut self, seconds: f32) {
+        let options = self.debris_options;
+        let damage = self.damage_options;
+        let floor = options.ground_level + options.radius;
+        let retained = 1.0 - options.surface.friction;
+        let t = seconds;
+
+        let FragmentsRowTable {
+            state,
+            health,
+            position,
+            velocity,
+            forces,
+            age,
+            rest_time,
+            chunk,
+            ..
+        } = &mut self.fragments;
+
+        // skip degenerate
+        for i in 1..state.len() {
+            match state[i] {
+                FragmentState::Attached => continue,
+                FragmentState::InactiveDebris => {
+                    age[i] += t;
+                    continue;
+                }
+                FragmentState::Debris => age[i] += t,
+            }
+            if chunk[i] != 0 {
+                continue;
+            }
+
+            let mass = health[i].max(MIN_DEBRIS_MASS);
+            let f = forces[i] + options.gravity * mass;
+            integrate_bare_body_seconds(&mut position[i], &mut velocity[i], 1.0 / mass, f, t);
+            forces[i] = glam::Vec3::ZERO;
+
+            if position[i].y < floor {
+                position[i].y = floor;
+                if velocity[i].y < 0.0 {
+                    health[i] = (health[i] - damage.impact(-velocity[i].y)).max(0.0);
+                    velocity[i].y *= -options.surface.restitution;
+                    velocity[i].x *= retained;
+                    velocity[i].z *= retained;
+                }
+            }
+
+            if velocity[i].length_squared() < options.settle_speed * options.settle_speed {
+                rest_time[i] += t;
+            } else {
+                rest_time[i] = 0.0;
+            }
+            if rest_time[i] >= options.settle_time {
+                state[i] = FragmentState::InactiveDebris;
+                velocity[i] = glam::Vec3::ZERO;
+            }
+        }
+
+        // the sides of chunks split during this step wait for the next one,
+        // whichever IDs they are created with
+        let live = (0..self.chunks.len() as u32)
+            // skip degenerate
+            .skip(1)
+            .filter(|&id| self.chunks[id as usize].is_alive())
+            .collect::<Vec<_>>();
+        for id in live {
+            self.integrate_chunk(id, t);
+        }
+    }
+
+    /// Advance the chunk `id` by `seconds`; see
+    /// [`FragmentSystem::integrate_debris`].
+    ///
+    /// The chunk touches the ground at the bottom of each of its fragments.
+    /// It is split in two by a hit faster than the split speed, through its
+    /// centre of mass and facing the hit.
+    fn integrate_chunk(&mut self, id: u32, seconds: f32) {
+        let options = self.debris_options;
+        let damage = self.damage_options;
+        let floor = options.ground_level + options.radius;
+        let t = seconds;
+
+        let chunk = &mut self.chunks[id as usize];
+        let Some(body) = chunk.body.as_mut() else {
+            return;
+        };
+        let indices = chunk
+            .members
+            .iter()
+            .map(
+                |member| unsafe { self.fragments.get_indirect_unchecked(member.fragment) } as usize,
+            )
+            .collect::<Vec<_>>();
+        let FragmentsRowTable {
+            state,
+            health,
+            position,
+            rotation,
+            velocity,
+            forces,
+            ..
+        } = &mut self.fragments;
+
+        let mut f = options.gravity / body.inv_mass;
+        let mut torque = glam::Vec3::ZERO;
+        for &i in &indices {
+            f += forces[i];
+            torque += (position[i] - body.position).cross(forces[i]);
+            forces[i] = glam::Vec3::ZERO;
+        }
+        body.integrate_seconds(f, torque, t);
+
+        // out of the ground by the deepest fragment, then bounce off every
+        // fragment touching it
+        let mut depth = 0.0f32;
+        for member in &chunk.members {
+            depth = depth.max(floor - body.to_world(member.offset).y);
+        }
+        if depth > 0.0 {
+            body.position.y += depth;
+        }
+
+        let mut hit = None::<(f32, glam::Vec3)>;
+        let mut damaged = false;
+        for (member, &i) in chunk.members.iter().zip(&indices) {
+            let center = body.to_world(member.offset);
+            if center.y > floor + CONTACT_SLOP {
+                continue;
+            }
+            let point = center - glam::Vec3::Y * options.radius;
+
+            let impact = -body.velocity_at(point).y;
+            if impact <= 0.0 {
+                continue;
+            }
+            let before = health[i];
+            health[i] = (health[i] - damage.impact(impact)).max(0.0);
+            damaged |= health[i] != before;
+
+            let k = body.inv_effective_mass(glam::Vec3::Y, point);
+            let normal = (1.0 + options.surface.restitution) * impact / k;
+            body.apply_impulse(glam::Vec3::Y * normal, point);
+
+            let slide = body.velocity_at(point).with_y(0.0);
+            if let Some(direction) = slide.try_normalize() {
+                let k = body.inv_effective_mass(direction, point);
+                let friction = options.surface.friction * slide.length() / k;
+                body.apply_impulse(-direction * friction, point);
+            }
+
+            if hit.is_none_or(|(fastest, _)| impact > fastest) {
+                hit = Some((impact, point));
+            }
+        }
+
+        let mut settled = true;
+        for (member, &i) in chunk.members.iter().zip(&indices) {
+            position[i] = body.to_world(member.offset);
+            rotation[i] = body.orientation * member.rotation;
+            velocity[i] = body.velocity_at(position[i]);
+            settled &= velocity[i].length_squared() < options.settle_speed * options.settle_speed;
+        }
+
+        if settled {
+            chunk.rest_time += t;
+        } else {
+            chunk.rest_time = 0.0;
+        }
+        if chunk.rest_time >= options.settle_time {
+            for &i in &indices {
+                state[i] = FragmentState::InactiveDebris;
+                velocity[i] = glam::Vec3::ZERO;
+            }
+            self.free_chunk(id);
+            return;
+        }
+
+        // the health of fragments is their mass
+        if damaged {
+            self.rebuild_chunk(id);
+        }
+        if let Some((impact, point)) = hit
+            && impact > options.split_speed
+        {
+            self.split_chunk(id, point);
+        }
+    }
+
+    /// Split the chunk `id` in two, through its centre of mass and facing
+    /// the world point `point`.
+    ///
+    /// Each side keeps moving as it did in the chunk. Sides with a single
+    /// fragment become independent debris; nothing happens if all fragments
+    /// are on the same side.
+    fn split_chunk(&mut self, id: u32, point: glam::Vec3) {
+        let chunk = &self.chunks[id as usize];
+        let body = chunk.body();
+        let normal = (point - body.position)
+            .try_normalize()
+            .unwrap_or(glam::Vec3::NEG_Y);
+
+        let (near, far): (Vec<_>, Vec<_>) = chunk
+            .members
+            .iter()
+            .map(|member| unsafe { self.fragments.get_indirect_unchecked(member.fragment) })
+            .partition(|&i| {
+                (self.fragments.position_slice()[i as usize] - body.position).dot(normal) > 0.0
+            });
+        if near.is_empty() || far.is_empty() {
+            return;
+        }
+
+        self.free_chunk(id);
+        for side in [near, far] {
+            if side.len() > 1 {
+                self.create_chunk(&side);
+            }
+        }
+    }
+
+    /// Free the [`FragmentState::InactiveDebris`] fragments past the limits
+    /// of the [`DespawnPolicy`], measuring distances from `camera`.
+    ///
+    /// Freeing moves rows of the fragments table: any data parallel to it
+    /// must follow its new order.
+    ///
+    /// # Returns
+    /// The amount of fragments freed.
+    pub fn despawn_debris(&mut self, camera: glam::Vec3) -> usize {
+        let policy = self.despawn_policy;
+        let table = &self.fragments;
+
+        let mut expired = Vec::new();
+        let mut kept = Vec::new();
+        let inactive = table
+            .handles()
+            .iter()
+            .zip(table.state_slice())
+            .zip(table.age_slice())
+            .zip(table.position_slice())
+            // skip degenerate
+            .skip(1)
+            .filter(|&(((_, &state), _), _)| state == FragmentState::InactiveDebris);
+
+        for (((&handle, _), &age), &position) in inactive {
+            let too_old = policy.max_age.is_some_and(|max| age > max);
+            let too_far = policy
+                .max_distance
+                .is_some_and(|max| position.distance_squared(camera) > max * max);
+
+            if too_old || too_far {
+                expired.push(handle);
+            } else {
+                kept.push((age, handle));
+            }
+        }
+        if let Some(max) = policy.max_count
+            && kept.len() > max
+        {
+            // oldest first
+            kept.sort_unstable_by(|a, b| b.0.total_cmp(&a.0));
+            let excess = kept.len() - max;
+            expired.extend(kept[..excess].iter().map(|&(_, handle)| handle));
+        }
+
+        let count = expired.len();
+        if count > 0 {
+            self.free_fragments(expired);
+        }
+        count
+    }
+
+    /// Evaluate the world transform of every [`FragmentState::Attached`]
+    /// fragment into its `position` and `rotation` columns.
+    ///
+    /// This mirrors the skinning in `fragment.vsh`: the position is the rest
+    /// offset plus the blend of the positions of the parent nodes, and the
+    /// rotation is the normalised blend of their rotations. Node positions
+    /// are taken at the last simulation step, while rendering interpolates
+    /// between steps.
+    ///
+    /// Fragments in any other state are left untouched.
+    pub fn world_transforms(&mut self, xpbd: &XpbdSystem) {
+        let FragmentsRowTable {
+            parents,
+            influence,
+            rest_offset,
+            state,
+            position,
+            rotation,
+            ..
+        } = &mut self.fragments;
+
+        // skip degenerate
+        for i in 1..parents.len() {
+            if state[i] != FragmentState::Attached {
+                continue;
+            }
+            (position[i], rotation[i]) = Self::skin(xpbd, parents[i], influence[i], rest_offset[i]);
+        }
+    }
+
+    /// The world transform of an attached fragment; see
+    /// [`FragmentSystem::world_transforms`].
+    fn skin(
+        xpbd: &XpbdSystem,
+        parents: [u32; 4],
+        weights: [f32; 4],
+        rest_offset: glam::Vec3,
+    ) -> (glam::Vec3, glam::Quat) {
+        let nodes = xpbd.nodes();
+        let positions = nodes.current_pos_slice();
+        let rotors = xpbd.rotor_system().rotations();
+
+        let mut position = rest_offset;
+        let mut rotation = glam::Vec4::ZERO;
+        for (&parent, &weight) in parents.iter().zip(&weights) {
+            // unused parents are the degenerate node, with no weight
+            let Some(index) = nodes.get_indirect(parent) else {
+                continue;
+            };
+            let rotor = rotors
+                .get(index as usize)
+                .copied()
+                .unwrap_or(glam::Quat::IDENTITY);
+
+            position += positions[index as usize] * weight;
+            rotation += glam::Vec4::from(rotor) * weight;
+        }
+        (position, glam::Quat::from_vec4(rotation).normalize())
+    }
+
     const LATTICE_SPATIAL_RESOLUTION: u32 = 1;
     const VOXEL_NEIGHBOR_QUERY_RADIUS: u32 = 4;
 
@@ -208,9 +1456,10 @@ impl FragmentSystem {
             node_hash
         };
 
-        let len = handles.len();
-        for _ in 0..len {
-            self.node_map.push(Vec::<u32>::new());
+        // node handles may be reused from removed structures
+        let len = handles.iter().max().map_or(0, |&max| max as usize + 1);
+        if self.node_map.len() < len {
+            self.node_map.resize_with(len, Vec::new);
         }
 
         let mut near_buf = Vec::with_capacity(4);
@@ -283,10 +1532,14 @@ impl FragmentSystem {
                 weights,
                 rest_offset,
                 FragmentState::Attached,
-                100.0, //todo; health
+                DEFAULT_FRAGMENT_HEALTH,
                 voxel,
+                glam::Quat::IDENTITY,
                 glam::Vec3::ZERO,
                 glam::Vec3::ZERO,
+                0.0,
+                0.0,
+                0,
             ));
             i += 1;
 
@@ -299,6 +1552,160 @@ impl FragmentSystem {
     }
 }
 
+impl Snapshot for FragmentState {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&(*self as u32));
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        match reader.read::<u32>()? {
+            0 => Ok(Self::Debris),
+            1 => Ok(Self::Attached),
+            2 => Ok(Self::InactiveDebris),
+            _ => Err(SnapshotError::Corrupt("invalid fragment state")),
+        }
+    }
+}
+
+impl Snapshot for FragmentsRowTable {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&HandleMaps::new(
+            self.handles(),
+            self.slots_map(),
+            self.free_list(),
+        ));
+        writer.write_slice(self.parents_slice());
+        writer.write_slice(self.influence_slice());
+        writer.write_slice(self.rest_offset_slice());
+        writer.write_slice(self.state_slice());
+        writer.write_slice(self.health_slice());
+        writer.write_slice(self.position_slice());
+        writer.write_slice(self.rotation_slice());
+        writer.write_slice(self.velocity_slice());
+        writer.write_slice(self.forces_slice());
+        writer.write_slice(self.age_slice());
+        writer.write_slice(self.rest_time_slice());
+        writer.write_slice(self.chunk_slice());
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        let maps = reader.read::<HandleMaps>()?;
+        let parents = reader.read_vec::<[u32; 4]>()?;
+        let influence = reader.read_vec::<[f32; 4]>()?;
+        let rest_offset = reader.read_vec::<glam::Vec3>()?;
+        let state = reader.read_vec::<FragmentState>()?;
+        let health = reader.read_vec::<f32>()?;
+        let position = reader.read_vec::<glam::Vec3>()?;
+        let rotation = reader.read_vec::<glam::Quat>()?;
+        let velocity = reader.read_vec::<glam::Vec3>()?;
+        let forces = reader.read_vec::<glam::Vec3>()?;
+        let age = reader.read_vec::<f32>()?;
+        let rest_time = reader.read_vec::<f32>()?;
+        let chunk = reader.read_vec::<u32>()?;
+
+        maps.check_column(&parents)?;
+        maps.check_column(&influence)?;
+        maps.check_column(&rest_offset)?;
+        maps.check_column(&state)?;
+        maps.check_column(&health)?;
+        maps.check_column(&position)?;
+        maps.check_column(&rotation)?;
+        maps.check_column(&velocity)?;
+        maps.check_column(&forces)?;
+        maps.check_column(&age)?;
+        maps.check_column(&rest_time)?;
+        maps.check_column(&chunk)?;
+
+        // skip degenerate; the fresh table has its own
+        let mut table = Self::with_capacity(maps.len());
+        for i in 1..maps.len() {
+            table.put((
+                parents[i],
+                influence[i],
+                rest_offset[i],
+                state[i],
+                health[i],
+                position[i],
+                rotation[i],
+                velocity[i],
+                forces[i],
+                age[i],
+                rest_time[i],
+                chunk[i],
+            ));
+        }
+        let (handles, slots_map, free_list) = maps.into_parts();
+        table.handles_mut().copy_from_slice(&handles);
+        *table.slots_map_mut() = slots_map;
+        *table.free_list_mut() = free_list;
+        Ok(table)
+    }
+}
+
+/// The fragments and their attachments.
+///
+/// Fragments disabled during the last frame are not saved.
+impl Snapshot for FragmentSystem {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.fragments);
+        writer.write(&self.debris_options);
+        writer.write(&self.despawn_policy);
+        writer.write(&self.damage_options);
+        writer.write(&self.node_map);
+        writer.write(&self.chunks);
+        writer.write(&self.free_chunks);
+
+        writer.write(&self.broken_links);
+        writer.write(&self.detach_threshold);
+
+        // sorted so that equal states give equal snapshots
+        let mut disabled_frags = self
+            .disabled_frags_alltime
+            .iter()
+            .copied()
+            .collect::<Vec<_>>();
+        disabled_frags.sort_unstable();
+        writer.write(&disabled_frags);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        let system = Self {
+            fragments: reader.read()?,
+            debris_options: reader.read()?,
+            despawn_policy: reader.read()?,
+            damage_options: reader.read()?,
+            node_map: reader.read()?,
+            chunks: reader.read()?,
+            free_chunks: reader.read()?,
+            broken_links: reader.read()?,
+            detach_threshold: reader.read()?,
+            disabled_frags_alltime: reader.read::<Vec<u32>>()?.into_iter().collect(),
+            disabled_frags_frame: Vec::new(),
+            node_strains: Vec::new(),
+        };
+
+        let chunk_count = system.chunks.len() as u32;
+        if chunk_count == 0 {
+            return Err(SnapshotError::Corrupt("missing degenerate chunk"));
+        }
+        let chunk_ids = system.fragments.chunk_slice().iter();
+        if chunk_ids
+            .chain(&system.free_chunks)
+            .any(|&id| id >= chunk_count)
+        {
+            return Err(SnapshotError::Corrupt("chunk ID out of range"));
+        }
+
+        let members = system.chunks.iter().flat_map(|chunk| &chunk.members);
+        for member in members {
+            if system.fragments.get_indirect(member.fragment).is_none() {
+                return Err(SnapshotError::Corrupt("chunk member is not a fragment"));
+            }
+        }
+        Ok(system)
+    }
+}
+
 #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct VoxelCell {
     pub x: i32,
@@ -481,3 +1888,561 @@ impl VoxelGrid {
         self.voxels.len()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use physics::xpbd::{XpbdLatticeBuilder, XpbdLinkOptions, XpbdNodeOptions, XpbdSolver};
+
+    /// A fixed cube lattice of side 2 around the origin, filled with the
+    /// fragments of a voxel grid of side 1.
+    fn fragment_cube() -> (XpbdSystem, FragmentSystem) {
+        let mut system = XpbdSystem::new(XpbdSolver::default());
+
+        let mut builder = XpbdLatticeBuilder::new();
+        let ids = (0..8)
+            .map(|i| {
+                let corner = glam::vec3(
+                    if i & 1 == 0 { -1.0 } else { 1.0 },
+                    if i & 2 == 0 { -1.0 } else { 1.0 },
+                    if i & 4 == 0 { -1.0 } else { 1.0 },
+                );
+                builder.node(XpbdNodeOptions::new(corner, 1.0).with_fixed(true))
+            })
+            .collect::<Vec<_>>();
+        for (i, &a) in ids.iter().enumerate() {
+            for &b in &ids[i + 1..] {
+                builder.link_nodes(a, b, XpbdLinkOptions::new(0.0));
+            }
+        }
+        system.import_lattice(builder);
+
+        let mut grid = VoxelGrid::new(|_| true, VoxelGridOptions::new(1.0, 1.0, 1.0, 2));
+        grid.build(glam::Vec3::splat(0.25));
+
+        // skip degenerate
+        let nodes = system.nodes();
+        let owners = nodes
+            .slots_map()
+            .iter()
+            .map(|v| v.saturating_sub(1))
+            .collect::<Vec<_>>();
+        let handles = &nodes.handles()[1..];
+        let positions = &nodes.current_pos_slice()[1..];
+
+        let mut fragments = FragmentSystem::new();
+        fragments.generate_fragments(&grid, (&owners, handles, positions));
+        assert_eq!(fragments.table().handles().len(), 9);
+
+        (system, fragments)
+    }
+
+    /// Move every node of `system` by the rigid transform `rotation` then
+    /// `translation`, and take a step so rotors follow.
+    fn move_rigidly(system: &mut XpbdSystem, rotation: glam::Quat, translation: glam::Vec3) {
+        // skip degenerate
+        for position in &mut system.nodes_mut().current_pos_mut_slice()[1..] {
+            *position = rotation * *position + translation;
+        }
+        let step_time = system.fixed_step().step_time();
+        system.update_seconds(step_time);
+    }
+
+    #[test]
+    fn fragment_world_transforms_at_rest() {
+        let (mut system, mut fragments) = fragment_cube();
+        move_rigidly(&mut system, glam::Quat::IDENTITY, glam::Vec3::ZERO);
+
+        let voxels = fragments.table().position_slice().to_vec();
+        fragments.world_transforms(&system);
+
+        let table = fragments.table();
+        // skip degenerate
+        for (i, &voxel) in voxels.iter().enumerate().skip(1) {
+            assert!(table.position_slice()[i].distance(voxel) < 1e-5);
+            assert!(table.rotation_slice()[i].angle_between(glam::Quat::IDENTITY) < 1e-3);
+        }
+    }
+
+    #[test]
+    fn fragment_world_transforms_follow_rigid_motion() {
+        let (mut system, mut fragments) = fragment_cube();
+        let voxels = fragments.table().position_slice().to_vec();
+
+        let translation = glam::vec3(3.0, -1.0, 2.0);
+        move_rigidly(&mut system, glam::Quat::IDENTITY, translation);
+        fragments.world_transforms(&system);
+        let table = fragments.table();
+        for (i, &voxel) in voxels.iter().enumerate().skip(1) {
+            let expected = voxel + translation;
+            assert!(table.position_slice()[i].distance(expected) < 1e-5);
+            assert!(table.rotation_slice()[i].angle_between(glam::Quat::IDENTITY) < 1e-3);
+        }
+
+        // the rest offset is not rotated, as in `fragment.vsh`: only the
+        // blend of the parent nodes turns
+        let rotation = glam::Quat::from_axis_angle(glam::vec3(1.0, 2.0, 0.5).normalize(), 0.6);
+        move_rigidly(&mut system, rotation, glam::Vec3::ZERO);
+        fragments.world_transforms(&system);
+        let table = fragments.table();
+        for (i, &voxel) in voxels.iter().enumerate().skip(1) {
+            let offset = table.rest_offset_slice()[i];
+            let expected = offset + rotation * (voxel - offset + translation);
+            assert!(table.position_slice()[i].distance(expected) < 1e-4);
+            assert!(table.rotation_slice()[i].angle_between(rotation) < 1e-3);
+        }
+    }
+
+    /// Put a fragment in `state`, weighted by `influence` between its
+    /// `parents`, and register it with them as [`FragmentSystem::generate_fragments`]
+    /// does.
+    fn put_fragment(
+        fragments: &mut FragmentSystem,
+        parents: [u32; 4],
+        influence: [f32; 4],
+        state: FragmentState,
+    ) -> u32 {
+        let handle = fragments.table_mut().put((
+            parents,
+            influence,
+            glam::Vec3::Y,
+            state,
+            DEFAULT_FRAGMENT_HEALTH,
+            glam::Vec3::ZERO,
+            glam::Quat::IDENTITY,
+            glam::Vec3::ZERO,
+            glam::Vec3::ZERO,
+            0.0,
+            0.0,
+            0,
+        ));
+        for parent in parents {
+            if fragments.node_map.len() <= parent as usize {
+                fragments
+                    .node_map
+                    .resize_with(parent as usize + 1, Vec::new);
+            }
+            fragments.node_map[parent as usize].push(handle);
+        }
+        handle
+    }
+
+    /// Put a free fragment of `age` seconds in `state`, at `position`.
+    fn put_debris(
+        fragments: &mut FragmentSystem,
+        state: FragmentState,
+        age: f32,
+        position: glam::Vec3,
+    ) -> u32 {
+        let handle = put_fragment(fragments, [0; 4], [0.0; 4], state);
+        let i = fragments.table().get_indirect(handle).unwrap() as usize;
+        fragments.table_mut().age_mut_slice()[i] = age;
+        fragments.table_mut().position_mut_slice()[i] = position;
+        handle
+    }
+
+    #[test]
+    fn fragment_despawn_oldest_first() {
+        let mut fragments = FragmentSystem::new();
+        let moving = put_debris(
+            &mut fragments,
+            FragmentState::Debris,
+            100.0,
+            glam::Vec3::ZERO,
+        );
+        let inactive = [3.0, 1.0, 5.0, 2.0, 4.0].map(|age| {
+            put_debris(
+                &mut fragments,
+                FragmentState::InactiveDebris,
+                age,
+                glam::Vec3::ZERO,
+            )
+        });
+
+        fragments.set_despawn_policy(DespawnPolicy::NEVER);
+        assert_eq!(fragments.despawn_debris(glam::Vec3::ZERO), 0);
+
+        // the three oldest go, debris in movement stays
+        fragments.set_despawn_policy(DespawnPolicy::NEVER.with_max_count(Some(2)));
+        assert_eq!(fragments.despawn_debris(glam::Vec3::ZERO), 3);
+        assert_eq!(fragments.despawn_debris(glam::Vec3::ZERO), 0);
+
+        let table = fragments.table();
+        let live = |handle| table.get_indirect(handle).is_some();
+        assert!(live(moving));
+        assert_eq!(inactive.map(live), [false, true, false, true, false]);
+    }
+
+    #[test]
+    fn fragment_despawn_by_age_and_distance() {
+        let mut fragments = FragmentSystem::new();
+        let far = glam::vec3(0.0, 0.0, 50.0);
+        let young = put_debris(
+            &mut fragments,
+            FragmentState::InactiveDebris,
+            1.0,
+            glam::Vec3::ZERO,
+        );
+        let old = put_debris(
+            &mut fragments,
+            FragmentState::InactiveDebris,
+            9.0,
+            glam::Vec3::ZERO,
+        );
+        let distant = put_debris(&mut fragments, FragmentState::InactiveDebris, 1.0, far);
+
+        let policy = DespawnPolicy::NEVER
+            .with_max_age(Some(5.0))
+            .with_max_distance(Some(10.0));
+        fragments.set_despawn_policy(policy);
+        assert_eq!(fragments.despawn_debris(glam::Vec3::ZERO), 2);
+
+        let table = fragments.table();
+        assert!(table.get_indirect(young).is_some());
+        assert!(table.get_indirect(old).is_none());
+        assert!(table.get_indirect(distant).is_none());
+    }
+
+    #[test]
+    fn fragment_chunks_weld_island_debris() {
+        // two islands: two bars, each anchored at one end
+        let mut system = XpbdSystem::new(XpbdSolver::default());
+        let mut builder = XpbdLatticeBuilder::new();
+        let ids = (0..4)
+            .map(|i| {
+                let node = XpbdNodeOptions::new(glam::vec3(i as f32 * 4.0, 0.0, 0.0), 1.0);
+                builder.node(node.with_fixed(i % 2 == 0))
+            })
+            .collect::<Vec<_>>();
+        builder.link_nodes(ids[0], ids[1], XpbdLinkOptions::new(0.0));
+        builder.link_nodes(ids[2], ids[3], XpbdLinkOptions::new(0.0));
+        let map = system.import_lattice(builder);
+        let [a, b, c, d] = map.nodes[..] else {
+            unreachable!()
+        };
+        let islands = system.islands();
+        assert_eq!(islands.island_of(a), islands.island_of(b));
+        assert_ne!(islands.island_of(a), islands.island_of(c));
+
+        let mut fragments = FragmentSystem::new();
+        let mut put = |parents: [u32; 4], influence: [f32; 4]| {
+            put_fragment(&mut fragments, parents, influence, FragmentState::Attached)
+        };
+        let first = [
+            put([a, 0, 0, 0], [1.0, 0.0, 0.0, 0.0]),
+            put([a, b, 0, 0], [0.5, 0.5, 0.0, 0.0]),
+            put([b, 0, 0, 0], [1.0, 0.0, 0.0, 0.0]),
+        ];
+        // the island is that of the first weighted parent
+        let second = [
+            put([c, 0, 0, 0], [1.0, 0.0, 0.0, 0.0]),
+            put([a, d, 0, 0], [0.0, 1.0, 0.0, 0.0]),
+        ];
+        // alone in its island
+        let alone = put([c, 0, 0, 0], [1.0, 0.0, 0.0, 0.0]);
+
+        // released in the same frame
+        for &handle in first.iter().chain(&second) {
+            let i = fragments.table().get_indirect(handle).unwrap();
+            fragments.table_mut().state_mut_slice()[i as usize] = FragmentState::Debris;
+            fragments.disabled_frags_frame.push(i);
+        }
+        fragments.release_debris(&system);
+
+        // released a frame later
+        let i = fragments.table().get_indirect(alone).unwrap();
+        fragments.table_mut().state_mut_slice()[i as usize] = FragmentState::Debris;
+        fragments.disabled_frags_frame.clear();
+        fragments.disabled_frags_frame.push(i);
+        fragments.release_debris(&system);
+
+        let table = fragments.table();
+        let chunk_of = |handle| table.chunk_slice()[table.get_indirect(handle).unwrap() as usize];
+        let first_chunk = chunk_of(first[0]);
+        let second_chunk = chunk_of(second[0]);
+        assert!(first_chunk != 0 && second_chunk != 0);
+        assert_ne!(first_chunk, second_chunk);
+        assert!(first.iter().all(|&handle| chunk_of(handle) == first_chunk));
+        assert!(
+            second
+                .iter()
+                .all(|&handle| chunk_of(handle) == second_chunk)
+        );
+        assert_eq!(chunk_of(alone), 0);
+
+        assert_eq!(fragments.chunk(first_chunk).unwrap().members().len(), 3);
+        assert_eq!(fragments.chunk(second_chunk).unwrap().members().len(), 2);
+    }
+
+    /// Weld debris fragments at `positions`, all moving at `velocity`, into a
+    /// chunk.
+    fn weld_debris(
+        fragments: &mut FragmentSystem,
+        positions: &[glam::Vec3],
+        velocity: glam::Vec3,
+    ) -> (u32, Vec<u32>) {
+        let handles = positions
+            .iter()
+            .map(|&position| put_debris(fragments, FragmentState::Debris, 0.0, position))
+            .collect::<Vec<_>>();
+        let table = fragments.table_mut();
+        let indices = handles
+            .iter()
+            .map(|&handle| table.get_indirect(handle).unwrap())
+            .collect::<Vec<_>>();
+        for &i in &indices {
+            table.velocity_mut_slice()[i as usize] = velocity;
+        }
+        let id = fragments.create_chunk(&indices);
+        assert_ne!(id, 0);
+        (id, handles)
+    }
+
+    /// Drop a row of four debris fragments welded into a chunk onto the
+    /// ground, fast enough to split it in two, after freeing `spare` chunks
+    /// created after it; returns where the fragments end up.
+    fn drop_splitting_chunk(spare: u32) -> Vec<glam::Vec3> {
+        let mut fragments = FragmentSystem::new();
+        let floor = fragments.debris_options().ground_level + fragments.debris_options().radius;
+        let fall = glam::vec3(0.0, -20.0, 0.0);
+
+        let row = (0..4)
+            .map(|i| glam::vec3(i as f32, floor + 0.01, 0.0))
+            .collect::<Vec<_>>();
+        let (_, handles) = weld_debris(&mut fragments, &row, fall);
+        for k in 0..spare {
+            let high = glam::vec3(0.0, 100.0, 10.0 * (k + 1) as f32);
+            weld_debris(&mut fragments, &[high, high + glam::Vec3::X], fall);
+        }
+        for id in 0..spare {
+            fragments.free_chunk(id + 2);
+        }
+
+        fragments.integrate_debris(1.0 / 60.0);
+        let table = fragments.table();
+        let chunks = handles
+            .iter()
+            .map(|&handle| table.chunk_slice()[table.get_indirect(handle).unwrap() as usize])
+            .collect::<Vec<_>>();
+        assert!(chunks[0] != 0 && chunks[3] != 0);
+        assert_ne!(chunks[0], chunks[3]);
+
+        handles
+            .iter()
+            .map(|&handle| table.position_slice()[table.get_indirect(handle).unwrap() as usize])
+            .collect()
+    }
+
+    #[test]
+    fn fragment_chunk_splits_integrate_once() {
+        // the far side may reuse the ID of a spare chunk, still to be
+        // integrated in the same step
+        let fresh = drop_splitting_chunk(0);
+        for spare in [1, 3] {
+            let reused = drop_splitting_chunk(spare);
+            for (a, b) in fresh.iter().zip(&reused) {
+                assert!(a.distance(*b) < 1e-6, "{a} != {b}");
+            }
+        }
+    }
+
+    #[test]
+    fn fragment_chunk_follows_its_members() {
+        let mut fragments = FragmentSystem::new();
+        let row = [glam::Vec3::ZERO, glam::Vec3::X, glam::vec3(2.0, 0.0, 0.0)];
+        let (id, handles) = weld_debris(&mut fragments, &row, glam::Vec3::ZERO);
+        let spin = glam::vec3(0.0, 0.0, 2.0);
+        let body = fragments.chunks[id as usize].body.as_mut().unwrap();
+        body.angular_velocity = spin;
+        let spun = *body;
+
+        // the rest of the chunk keeps turning around the old centre of mass
+        fragments.free_fragments(vec![handles[2]]);
+        let chunk = fragments.chunk(id).unwrap();
+        let body = chunk.body();
+        assert_eq!(chunk.members().len(), 2);
+        assert!(body.position.distance(glam::vec3(0.5, 0.0, 0.0)) < 1e-5);
+        assert!((body.inv_mass - 0.5 / DEFAULT_FRAGMENT_HEALTH).abs() < 1e-9);
+        assert!(body.velocity.distance(spun.velocity_at(body.position)) < 1e-5);
+        assert_eq!(body.angular_velocity, spin);
+        for (member, &position) in chunk.members().iter().zip(&row) {
+            assert!(body.to_world(member.offset).distance(position) < 1e-5);
+        }
+
+        // lighter members move the centre of mass
+        let i = fragments.table().get_indirect(handles[0]).unwrap() as usize;
+        fragments.table_mut().health_mut_slice()[i] = DEFAULT_FRAGMENT_HEALTH / 3.0;
+        fragments.rebuild_chunk(id);
+        let body = fragments.chunk(id).unwrap().body();
+        assert!(body.position.distance(glam::vec3(0.75, 0.0, 0.0)) < 1e-5);
+
+        // a single fragment left is plain debris
+        fragments.free_fragments(vec![handles[1]]);
+        assert!(fragments.chunk(id).is_none());
+        let i = fragments.table().get_indirect(handles[0]).unwrap() as usize;
+        assert_eq!(fragments.table().chunk_slice()[i], 0);
+    }
+
+    /// A fixed bar of two nodes, with one fragment halfway between them.
+    fn fragment_bar() -> (XpbdSystem, FragmentSystem, u32) {
+        let mut system = XpbdSystem::new(XpbdSolver::default());
+        let mut builder = XpbdLatticeBuilder::new();
+        let a = builder.node(XpbdNodeOptions::new(glam::Vec3::ZERO, 1.0).with_fixed(true));
+        let b = builder.node(XpbdNodeOptions::new(glam::Vec3::X, 1.0).with_fixed(true));
+        builder.link_nodes(a, b, XpbdLinkOptions::new(0.0));
+        let map = system.import_lattice(builder);
+
+        let mut fragments = FragmentSystem::new();
+        let handle = put_fragment(
+            &mut fragments,
+            [map.nodes[0], map.nodes[1], 0, 0],
+            [0.5, 0.5, 0.0, 0.0],
+            FragmentState::Attached,
+        );
+        let i = fragments.table().get_indirect(handle).unwrap() as usize;
+        fragments.table_mut().position_mut_slice()[i] = glam::vec3(0.5, 1.0, 0.0);
+        (system, fragments, handle)
+    }
+
+    /// Move the second node of the bar of [`fragment_bar`] to `length`, and
+    /// let its link yield to `rest_length`.
+    fn stretch_bar(system: &mut XpbdSystem, length: f32, rest_length: f32) {
+        let nodes = system.nodes_mut().current_pos_mut_slice();
+        *nodes.last_mut().unwrap() = glam::vec3(length, 0.0, 0.0);
+        let links = system.links_mut().rest_length_mut_slice();
+        *links.last_mut().unwrap() = rest_length;
+    }
+
+    fn health_of(fragments: &FragmentSystem, handle: u32) -> f32 {
+        let table = fragments.table();
+        table.health_slice()[table.get_indirect(handle).unwrap() as usize]
+    }
+
+    fn state_of(fragments: &FragmentSystem, handle: u32) -> FragmentState {
+        let table = fragments.table();
+        table.state_slice()[table.get_indirect(handle).unwrap() as usize]
+    }
+
+    #[test]
+    fn fragment_damage_explicit() {
+        let (system, mut fragments, handle) = fragment_bar();
+
+        fragments.damage(handle, 40.0);
+        assert_eq!(
+            health_of(&fragments, handle),
+            DEFAULT_FRAGMENT_HEALTH - 40.0
+        );
+        fragments.update_damage_seconds(&system, 1.0);
+        assert_eq!(state_of(&fragments, handle), FragmentState::Attached);
+
+        fragments.damage_area(&system, glam::vec3(0.5, 1.0, 0.0), 1.0, 1000.0);
+        assert_eq!(health_of(&fragments, handle), 0.0);
+        fragments.update_damage_seconds(&system, 1.0);
+        assert_eq!(state_of(&fragments, handle), FragmentState::Debris);
+        assert_eq!(fragments.frame_disabled_frags_direct().len(), 1);
+    }
+
+    #[test]
+    fn fragment_damage_by_strain() {
+        let (mut system, mut fragments, handle) = fragment_bar();
+
+        // at rest, nothing is lost
+        fragments.update_damage_seconds(&system, 1.0);
+        assert_eq!(health_of(&fragments, handle), DEFAULT_FRAGMENT_HEALTH);
+
+        // stretched by half its length
+        stretch_bar(&mut system, 1.5, 1.0);
+        fragments.update_damage_seconds(&system, 0.01);
+        assert!(health_of(&fragments, handle) < DEFAULT_FRAGMENT_HEALTH);
+
+        fragments.update_damage_seconds(&system, 1.0);
+        assert_eq!(health_of(&fragments, handle), 0.0);
+        assert_eq!(state_of(&fragments, handle), FragmentState::Debris);
+
+        // a link that yielded to its length is not strained
+        let (mut system, mut fragments, handle) = fragment_bar();
+        stretch_bar(&mut system, 1.5, 1.5);
+        fragments.update_damage_seconds(&system, 1.0);
+        assert_eq!(health_of(&fragments, handle), DEFAULT_FRAGMENT_HEALTH);
+    }
+
+    #[test]
+    fn fragment_damage_by_impact() {
+        let mut fragments = FragmentSystem::new();
+        let floor = fragments.debris_options().ground_level + fragments.debris_options().radius;
+        let handle = put_debris(
+            &mut fragments,
+            FragmentState::Debris,
+            0.0,
+            glam::vec3(0.0, floor + 0.01, 0.0),
+        );
+        let i = fragments.table().get_indirect(handle).unwrap() as usize;
+        fragments.table_mut().velocity_mut_slice()[i] = glam::vec3(0.0, -10.0, 0.0);
+
+        // hits the ground after gravity sped it up
+        fragments.integrate_debris(1.0 / 60.0);
+        let damage = fragments.damage_options().impact(10.0 + 9.81 / 60.0);
+        assert!(damage > 0.0);
+        let expected = DEFAULT_FRAGMENT_HEALTH - damage;
+        assert!((health_of(&fragments, handle) - expected).abs() < 1e-3);
+
+        fragments.table_mut().velocity_mut_slice()[i] = glam::vec3(0.0, -100.0, 0.0);
+        fragments.integrate_debris(1.0 / 60.0);
+        assert_eq!(health_of(&fragments, handle), 0.0);
+    }
+
+    #[test]
+    fn fragment_detach_by_held_weight() {
+        // a fixed triangle, with a tail hanging off one of its corners
+        let mut system = XpbdSystem::new(XpbdSolver::default());
+        let mut builder = XpbdLatticeBuilder::new();
+        let ids = [
+            glam::vec3(0.0, 0.0, 0.0),
+            glam::vec3(1.0, 0.0, 0.0),
+            glam::vec3(0.0, 1.0, 0.0),
+            glam::vec3(-1.0, 1.0, 0.0),
+        ]
+        .map(|position| builder.node(XpbdNodeOptions::new(position, 1.0).with_fixed(true)));
+        let [a, b, c, d] = ids;
+        builder.link_nodes(a, b, XpbdLinkOptions::new(0.0));
+        builder.link_nodes(b, c, XpbdLinkOptions::new(0.0));
+        builder.link_nodes(c, a, XpbdLinkOptions::new(0.0));
+        let tail = builder.link_nodes(c, d, XpbdLinkOptions::new(0.0));
+        let map = system.import_lattice(builder);
+        let [a, _, c, d] = map.nodes[..] else {
+            unreachable!()
+        };
+
+        let mut fragments = FragmentSystem::new();
+        let mut put = |parents: [u32; 4], influence: [f32; 4]| {
+            put_fragment(&mut fragments, parents, influence, FragmentState::Attached)
+        };
+        // held by half its weight, at the threshold
+        let held = put([a, d, 0, 0], [0.5, 0.5, 0.0, 0.0]);
+        // held by a fifth of its weight, through a weakened node
+        let loose = put([d, c, 0, 0], [0.8, 0.2, 0.0, 0.0]);
+
+        fragments.set_detach_threshold(0.5);
+        fragments.world_transforms(&system);
+        let before = fragments.table().position_slice().to_vec();
+
+        system.break_constraint(map.links[tail as usize]);
+        system.update_seconds(0.0);
+        fragments.handle_constraint_break(&system);
+        fragments.world_transforms(&system);
+
+        let table = fragments.table();
+        let index = |handle| table.get_indirect(handle).unwrap() as usize;
+        assert_eq!(table.state_slice()[index(held)], FragmentState::Attached);
+        assert_eq!(table.state_slice()[index(loose)], FragmentState::Debris);
+        assert_eq!(
+            fragments.frame_disabled_frags_direct(),
+            [index(loose) as u32]
+        );
+
+        // the weight of the broken parent moves onto the other, in place
+        assert_eq!(table.influence_slice()[index(held)], [1.0, 0.0, 0.0, 0.0]);
+        let position = table.position_slice()[index(held)];
+        assert!(position.distance(before[index(held)]) < 1e-5);
+    }
+}
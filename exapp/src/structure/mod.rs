@@ -1,11 +1,17 @@
 pub mod fragment;
 
-use physics::xpbd::{XpbdLatticeBuilder, XpbdLinkOptions, XpbdNodeOptions as Node};
+use physics::{
+    material::Material,
+    xpbd::{XpbdLatticeBuilder, XpbdLinkOptions, XpbdNodeOptions as Node},
+};
 
 #[allow(unused_imports)]
 pub use fragment::{FragmentState, FragmentSystem};
 
 // height is per floor, not total building; todo: docs
+//
+// groups: `anchors` (fixed nodes), `floor:N` (nodes of floor N, from 1),
+// `pillars` and `braces` (links)
 pub fn create_structure_lattice(
     origin: glam::Vec3,
     width: f32,
@@ -19,15 +25,19 @@ pub fn create_structure_lattice(
     // include 4 anchor nodes of the building
     let total_node_count = FLOOR_NODE_COUNT * floors as usize + 4;
 
-    const MASS: f32 = 100.0;
+    // swap these to change the make of the whole building
+    const FRAME: Material = Material::STEEL;
+    const WALLS: Material = Material::CONCRETE;
+    const BRACES: Material = Material::TIMBER;
 
-    const VERY_STIFF_COMPL: f32 = 0.1e-6;
-    const STIFF_COMPL: f32 = 0.2e-5;
-    const SOFT_COMPL: f32 = 0.1e-3;
+    // nodes keep the mass of 100 the building was tuned with
+    const NODE_VOLUME: f32 = 100.0 / WALLS.density;
 
-    const STRONG_LINK: XpbdLinkOptions = XpbdLinkOptions::new(VERY_STIFF_COMPL);
-    const MID_LINK: XpbdLinkOptions = XpbdLinkOptions::new(STIFF_COMPL);
-    const WEAK_LINK: XpbdLinkOptions = XpbdLinkOptions::new(SOFT_COMPL);
+    const STRONG_LINK: XpbdLinkOptions = XpbdLinkOptions::from_material(&FRAME);
+    const MID_LINK: XpbdLinkOptions = XpbdLinkOptions::from_material(&WALLS);
+    const WEAK_LINK: XpbdLinkOptions = XpbdLinkOptions::from_material(&BRACES);
+
+    let node = |pos: glam::Vec3| Node::from_material(pos, NODE_VOLUME, &WALLS);
 
     let mut lattice = XpbdLatticeBuilder::with_capacity(total_node_count);
     let w = width / 2.0;
@@ -35,11 +45,15 @@ pub fn create_structure_lattice(
     let o = origin;
 
     // anchor nodes
-    let bottom_l_b = lattice.node(Node::new(o + glam::vec3(-w, 0.0, -d), MASS).with_fixed(true));
-    let bottom_r_b = lattice.node(Node::new(o + glam::vec3(w, 0.0, -d), MASS).with_fixed(true));
-    let bottom_r_f = lattice.node(Node::new(o + glam::vec3(w, 0.0, d), MASS).with_fixed(true));
-    let bottom_l_f = lattice.node(Node::new(o + glam::vec3(-w, 0.0, d), MASS).with_fixed(true));
-    let mid = lattice.node(Node::new(o + glam::vec3(0.0, 0.0, 0.0), MASS).with_fixed(true));
+    let bottom_l_b = lattice.node(node(o + glam::vec3(-w, 0.0, -d)).with_fixed(true));
+    let bottom_r_b = lattice.node(node(o + glam::vec3(w, 0.0, -d)).with_fixed(true));
+    let bottom_r_f = lattice.node(node(o + glam::vec3(w, 0.0, d)).with_fixed(true));
+    let bottom_l_f = lattice.node(node(o + glam::vec3(-w, 0.0, d)).with_fixed(true));
+    let mid = lattice.node(node(o + glam::vec3(0.0, 0.0, 0.0)).with_fixed(true));
+    lattice.tag_nodes(
+        "anchors",
+        &[bottom_l_b, bottom_r_b, bottom_r_f, bottom_l_f, mid],
+    );
     {
         lattice.link_nodes(bottom_l_b, bottom_r_b, STRONG_LINK);
         lattice.link_nodes(bottom_r_b, bottom_r_f, STRONG_LINK);
@@ -62,10 +76,10 @@ pub fn create_structure_lattice(
         let ceiling_y = height * (i + 1) as f32;
         let mid_y = ceiling_y - height * 0.5;
 
-        let back_left = lattice.node(Node::new(o + glam::vec3(-w, ceiling_y, -d), MASS));
-        let back_right = lattice.node(Node::new(o + glam::vec3(w, ceiling_y, -d), MASS));
-        let front_right = lattice.node(Node::new(o + glam::vec3(w, ceiling_y, d), MASS));
-        let front_left = lattice.node(Node::new(o + glam::vec3(-w, ceiling_y, d), MASS));
+        let back_left = lattice.node(node(o + glam::vec3(-w, ceiling_y, -d)));
+        let back_right = lattice.node(node(o + glam::vec3(w, ceiling_y, -d)));
+        let front_right = lattice.node(node(o + glam::vec3(w, ceiling_y, d)));
+        let front_left = lattice.node(node(o + glam::vec3(-w, ceiling_y, d)));
 
         // top loop
         {
@@ -76,16 +90,19 @@ pub fn create_structure_lattice(
         }
         // pillars
         {
-            lattice.link_nodes(back_left, last_top[0], STRONG_LINK);
-            lattice.link_nodes(back_right, last_top[1], STRONG_LINK);
-            lattice.link_nodes(front_right, last_top[2], STRONG_LINK);
-            lattice.link_nodes(front_left, last_top[3], STRONG_LINK);
+            let pillars = [
+                lattice.link_nodes(back_left, last_top[0], STRONG_LINK),
+                lattice.link_nodes(back_right, last_top[1], STRONG_LINK),
+                lattice.link_nodes(front_right, last_top[2], STRONG_LINK),
+                lattice.link_nodes(front_left, last_top[3], STRONG_LINK),
+            ];
+            lattice.tag_links("pillars", &pillars);
         }
 
-        let c_left = lattice.node(Node::new(o + glam::vec3(-w, mid_y, 0.0), MASS));
-        let c_right = lattice.node(Node::new(o + glam::vec3(w, mid_y, 0.0), MASS));
-        let c_front = lattice.node(Node::new(o + glam::vec3(0.0, mid_y, d), MASS));
-        let c_back = lattice.node(Node::new(o + glam::vec3(0.0, mid_y, -d), MASS));
+        let c_left = lattice.node(node(o + glam::vec3(-w, mid_y, 0.0)));
+        let c_right = lattice.node(node(o + glam::vec3(w, mid_y, 0.0)));
+        let c_front = lattice.node(node(o + glam::vec3(0.0, mid_y, d)));
+        let c_back = lattice.node(node(o + glam::vec3(0.0, mid_y, -d)));
 
         // side cross
         {
@@ -111,8 +128,25 @@ pub fn create_structure_lattice(
         }
 
         // floors with no intermediate center point
-        lattice.link_nodes(back_left, front_right, WEAK_LINK);
-        lattice.link_nodes(back_right, front_left, WEAK_LINK);
+        let braces = [
+            lattice.link_nodes(back_left, front_right, WEAK_LINK),
+            lattice.link_nodes(back_right, front_left, WEAK_LINK),
+        ];
+        lattice.tag_links("braces", &braces);
+
+        lattice.tag_nodes(
+            &format!("floor:{}", i + 1),
+            &[
+                back_left,
+                back_right,
+                front_right,
+                front_left,
+                c_left,
+                c_right,
+                c_front,
+                c_back,
+            ],
+        );
 
         last_top = [back_left, back_right, front_right, front_left];
     }
@@ -0,0 +1,271 @@
+use ethel::state::data::Column;
+
+use crate::{
+    snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter},
+    xpbd::{LinkNodes, LinksRowTable},
+};
+
+/// Maximum amount of colours a [`ConstraintColouring`] hands out.
+///
+/// Links that cannot be given any of these colours are put in the
+/// [overflow](ConstraintColouring::overflow) batch instead.
+pub const MAX_COLOURS: u32 = u64::BITS;
+
+const OVERFLOW: u32 = u32::MAX;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct ColourSlot {
+    colour: u32,
+    position: u32,
+    relation: LinkNodes,
+}
+
+impl Default for ColourSlot {
+    fn default() -> Self {
+        Self {
+            colour: OVERFLOW,
+            position: 0,
+            relation: LinkNodes::default(),
+        }
+    }
+}
+
+/// Greedy colouring of a constraint graph.
+///
+/// Links are partitioned into batches so that no two links in the same batch
+/// share a node. All links of a batch can be solved at the same time.
+///
+/// The colouring is updated incrementally: links are coloured as they are
+/// inserted and freed from their batch as they are removed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConstraintColouring {
+    batches: Vec<Vec<u32>>,
+    overflow: Vec<u32>,
+
+    // sparse map by link handle; degenerate relation if untracked
+    slots: Vec<ColourSlot>,
+    // sparse map by node handle of the colours used by its links
+    node_colours: Vec<u64>,
+}
+
+impl ConstraintColouring {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The batches of link handles, one per colour.
+    ///
+    /// No two links in the same batch share a node.
+    pub fn batches(&self) -> &[Vec<u32>] {
+        &self.batches
+    }
+
+    /// Links that could not be coloured.
+    ///
+    /// These may share nodes with one another and must be solved serially.
+    pub fn overflow(&self) -> &[u32] {
+        &self.overflow
+    }
+
+    /// Colour the links `handles` of the `links` table.
+    pub fn insert_links(&mut self, handles: &[u32], links: &LinksRowTable) {
+        for &handle in handles {
+            let index = unsafe { links.get_indirect_unchecked(handle) };
+            let relation = links.relation_slice()[index as usize];
+            self.insert(handle, relation);
+        }
+    }
+
+    /// Remove the links `handles` from their batch.
+    ///
+    /// Links that are not coloured are ignored.
+    pub fn remove_links(&mut self, handles: &[u32]) {
+        for &handle in handles {
+            self.remove(handle);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.batches.iter_mut().for_each(Vec::clear);
+        self.overflow.clear();
+        self.slots.clear();
+        self.node_colours.clear();
+    }
+
+    fn insert(&mut self, handle: u32, relation: LinkNodes) {
+        let LinkNodes(a, b) = relation;
+        let len = a.max(b) as usize + 1;
+        if self.node_colours.len() < len {
+            self.node_colours.resize(len, 0);
+        }
+        let len = handle as usize + 1;
+        if self.slots.len() < len {
+            self.slots.resize(len, ColourSlot::default());
+        }
+
+        let used = self.node_colours[a as usize] | self.node_colours[b as usize];
+        let colour = (!used).trailing_zeros();
+
+        let batch = if colour < MAX_COLOURS {
+            self.node_colours[a as usize] |= 1 << colour;
+            self.node_colours[b as usize] |= 1 << colour;
+
+            if self.batches.len() <= colour as usize {
+                self.batches.resize_with(colour as usize + 1, Vec::new);
+            }
+            &mut self.batches[colour as usize]
+        } else {
+            &mut self.overflow
+        };
+
+        self.slots[handle as usize] = ColourSlot {
+            colour: if colour < MAX_COLOURS {
+                colour
+            } else {
+                OVERFLOW
+            },
+            position: batch.len() as u32,
+            relation,
+        };
+        batch.push(handle);
+    }
+
+    fn remove(&mut self, handle: u32) {
+        let Some(slot) = self.slots.get_mut(handle as usize) else {
+            return;
+        };
+        let ColourSlot {
+            colour,
+            position,
+            relation: LinkNodes(a, b),
+        } = std::mem::take(slot);
+        if a == 0 && b == 0 {
+            return;
+        }
+
+        let batch = if colour < MAX_COLOURS {
+            self.node_colours[a as usize] &= !(1 << colour);
+            self.node_colours[b as usize] &= !(1 << colour);
+            &mut self.batches[colour as usize]
+        } else {
+            &mut self.overflow
+        };
+
+        batch.swap_remove(position as usize);
+        if let Some(&moved) = batch.get(position as usize) {
+            self.slots[moved as usize].position = position;
+        }
+    }
+}
+
+impl Snapshot for ColourSlot {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.colour);
+        writer.write(&self.position);
+        writer.write(&self.relation);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self {
+            colour: reader.read()?,
+            position: reader.read()?,
+            relation: reader.read()?,
+        })
+    }
+}
+
+impl Snapshot for ConstraintColouring {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.batches);
+        writer.write(&self.overflow);
+        writer.write(&self.slots);
+        writer.write(&self.node_colours);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        let colouring = Self {
+            batches: reader.read()?,
+            overflow: reader.read()?,
+            slots: reader.read()?,
+            node_colours: reader.read()?,
+        };
+        if colouring.batches.len() > MAX_COLOURS as usize {
+            return Err(SnapshotError::Corrupt("too many colours"));
+        }
+
+        // every coloured link must sit in its batch, where its slot says
+        let batches = colouring.batches.iter().enumerate();
+        let batches = batches.map(|(colour, batch)| (colour as u32, batch));
+        for (colour, batch) in batches.chain([(OVERFLOW, &colouring.overflow)]) {
+            for (position, &handle) in batch.iter().enumerate() {
+                let Some(slot) = colouring.slots.get(handle as usize) else {
+                    return Err(SnapshotError::Corrupt("coloured link has no slot"));
+                };
+                if slot.colour != colour || slot.position != position as u32 {
+                    return Err(SnapshotError::Corrupt("colour slot does not match batch"));
+                }
+
+                let LinkNodes(a, b) = slot.relation;
+                if a.max(b) as usize >= colouring.node_colours.len() {
+                    return Err(SnapshotError::Corrupt("coloured link node out of range"));
+                }
+            }
+        }
+        Ok(colouring)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xpbd::{NodesRowTable, XpbdLatticeBuilder, XpbdLinkOptions, XpbdNodeOptions};
+
+    fn assert_disjoint(colouring: &ConstraintColouring, links: &LinksRowTable) {
+        for batch in colouring.batches() {
+            let mut seen = Vec::new();
+            for &handle in batch {
+                let index = links.get_indirect(handle).unwrap();
+                let LinkNodes(a, b) = links.relation_slice()[index as usize];
+                assert!(!seen.contains(&a) && !seen.contains(&b));
+                seen.extend([a, b]);
+            }
+        }
+    }
+
+    #[test]
+    fn colouring_batches_share_no_nodes() {
+        const NODE: XpbdNodeOptions = XpbdNodeOptions::new(glam::Vec3::ONE, 1.0);
+        const LINK: XpbdLinkOptions = XpbdLinkOptions::new(1.0);
+
+        // fully connected graph of 5 nodes
+        let mut builder = XpbdLatticeBuilder::new();
+        let ids = (0..5).map(|_| builder.node(NODE)).collect::<Vec<_>>();
+        for (i, &a) in ids.iter().enumerate() {
+            for &b in &ids[i + 1..] {
+                builder.link_nodes(a, b, LINK);
+            }
+        }
+
+        let mut nodes = NodesRowTable::new();
+        let mut links = LinksRowTable::new();
+        let map = builder.export(&mut nodes, &mut links);
+
+        let mut colouring = ConstraintColouring::new();
+        colouring.insert_links(&map.links, &links);
+
+        let coloured = colouring.batches().iter().map(Vec::len).sum::<usize>();
+        assert_eq!(coloured, map.links.len());
+        assert!(colouring.overflow().is_empty());
+        assert_disjoint(&colouring, &links);
+
+        colouring.remove_links(&map.links[..4]);
+        let coloured = colouring.batches().iter().map(Vec::len).sum::<usize>();
+        assert_eq!(coloured, map.links.len() - 4);
+
+        // freed colours are handed out again
+        colouring.insert_links(&map.links[..4], &links);
+        let coloured = colouring.batches().iter().map(Vec::len).sum::<usize>();
+        assert_eq!(coloured, map.links.len());
+        assert_disjoint(&colouring, &links);
+    }
+}
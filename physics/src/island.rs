@@ -0,0 +1,915 @@
+use ethel::state::data::Column;
+
+use crate::{
+    snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter},
+    xpbd::{LatticeIds, LinkNodes, LinksRowTable, NodesRowTable},
+};
+
+/// A set of nodes connected to one another through intact links.
+#[derive(Clone, Debug, Default)]
+pub struct Island {
+    nodes: Vec<u32>,
+    anchored: bool,
+
+    asleep: bool,
+    still_frames: u32,
+}
+
+impl Island {
+    /// Handles of the nodes in this island.
+    #[inline]
+    pub fn nodes(&self) -> &[u32] {
+        &self.nodes
+    }
+
+    /// Returns `true` if at least one node of this island is fixed.
+    ///
+    /// An island that is not anchored is free-floating: nothing holds it to
+    /// the structure it was part of.
+    #[inline]
+    pub fn is_anchored(&self) -> bool {
+        self.anchored
+    }
+
+    /// Returns `true` if this island is asleep: its nodes are skipped by the
+    /// solver until it is woken up.
+    #[inline]
+    pub fn is_asleep(&self) -> bool {
+        self.asleep
+    }
+
+    #[inline]
+    fn is_alive(&self) -> bool {
+        !self.nodes.is_empty()
+    }
+}
+
+pub const DEFAULT_SLEEP_SPEED: f32 = 0.05;
+pub const DEFAULT_SLEEP_ERROR: f32 = 0.01;
+pub const DEFAULT_SLEEP_FRAMES: u32 = 60;
+
+/// Thresholds under which an island is put to sleep.
+///
+/// An island falls asleep once the speed of all of its nodes stays under
+/// `speed`, and the length error of all of its links stays under `error`, for
+/// `frames` consecutive updates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SleepOptions {
+    pub speed: f32,
+    pub error: f32,
+    pub frames: u32,
+}
+
+impl SleepOptions {
+    pub const fn new(speed: f32, error: f32, frames: u32) -> Self {
+        Self {
+            speed,
+            error,
+            frames,
+        }
+    }
+}
+
+impl Default for SleepOptions {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_SLEEP_SPEED,
+            DEFAULT_SLEEP_ERROR,
+            DEFAULT_SLEEP_FRAMES,
+        )
+    }
+}
+
+/// Tracks the connected components of the lattices in a [`NodesRowTable`]
+/// and [`LinksRowTable`] pair.
+///
+/// Islands are identified by a stable ID, which stays valid until the island
+/// is split or merged. ID `0` is degenerate and never refers to an island.
+#[derive(Clone, Debug)]
+pub struct IslandTracker {
+    islands: Vec<Island>,
+    free_islands: Vec<u32>,
+
+    // sparse maps by node handle
+    node_island: Vec<u32>,
+    anchors: Vec<bool>,
+    adjacency: Vec<Vec<(u32, u32)>>,
+
+    // sparse map by link handle; degenerate LinkNodes(0, 0) if untracked
+    link_nodes: Vec<LinkNodes>,
+
+    // islands created by splits since the last clear
+    frame_splits: Vec<u32>,
+
+    queue: Vec<u32>,
+    back_queue: Vec<u32>,
+    visited: Vec<u32>,
+    visit_stamp: u32,
+}
+
+impl Default for IslandTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IslandTracker {
+    pub fn new() -> Self {
+        Self {
+            // account for degenerate
+            islands: vec![Island::default()],
+            free_islands: Vec::new(),
+            node_island: vec![0],
+            anchors: vec![false],
+            adjacency: vec![Vec::new()],
+            link_nodes: vec![LinkNodes::default()],
+            frame_splits: Vec::new(),
+            queue: Vec::new(),
+            back_queue: Vec::new(),
+            visited: vec![0],
+            visit_stamp: 0,
+        }
+    }
+
+    /// Get the island `id`.
+    ///
+    /// Returns `None` if `id` does not refer to a live island.
+    pub fn island(&self, id: u32) -> Option<&Island> {
+        self.islands
+            .get(id as usize)
+            .filter(|island| island.is_alive())
+    }
+
+    /// Get the ID of the island `node` belongs to.
+    ///
+    /// Returns `None` if `node` is not tracked.
+    pub fn island_of(&self, node: u32) -> Option<u32> {
+        self.node_island
+            .get(node as usize)
+            .copied()
+            .filter(|&id| id != 0)
+    }
+
+    /// Get the amount of intact links of `node`.
+    ///
+    /// Links stop counting as soon as they are handled as broken. Returns `0`
+    /// if `node` is not tracked.
+    pub fn link_count(&self, node: u32) -> usize {
+        self.adjacency.get(node as usize).map_or(0, Vec::len)
+    }
+
+    /// Iterate over all live islands and their IDs.
+    pub fn islands(&self) -> impl Iterator<Item = (u32, &Island)> {
+        self.islands
+            .iter()
+            .enumerate()
+            .filter(|(_, island)| island.is_alive())
+            .map(|(id, island)| (id as u32, island))
+    }
+
+    /// Returns the IDs of the islands that were split off from another
+    /// island since the last [`IslandTracker::clear_frame_splits`].
+    ///
+    /// The island that was split keeps its ID; only the part that broke off
+    /// is reported here. That is the unanchored side if only one side is
+    /// still anchored, or the smaller side otherwise.
+    pub fn frame_splits(&self) -> &[u32] {
+        &self.frame_splits
+    }
+
+    pub fn clear_frame_splits(&mut self) {
+        self.frame_splits.clear();
+    }
+
+    /// Start tracking the nodes and links of a freshly exported lattice.
+    ///
+    /// A node is considered an anchor if its inverse mass is zero.
+    ///
+    /// Islands the lattice links into, through its external nodes, are
+    /// merged with it and woken up.
+    pub fn insert_lattice(
+        &mut self,
+        lattice: &LatticeIds,
+        nodes: &mut NodesRowTable,
+        links: &LinksRowTable,
+    ) {
+        for &node in &lattice.nodes {
+            self.reserve_node(node);
+
+            let index = unsafe { nodes.get_indirect_unchecked(node) };
+            self.anchors[node as usize] = nodes.inv_mass_slice()[index as usize] == 0.0;
+        }
+
+        for &link in &lattice.links {
+            let index = unsafe { links.get_indirect_unchecked(link) };
+            let relation = links.relation_slice()[index as usize];
+            self.insert_link(link, relation);
+        }
+
+        // links to external nodes may join islands that are already tracked
+        for &link in &lattice.links {
+            let LinkNodes(a, b) = self.link_nodes[link as usize];
+            let island = self.node_island[a as usize];
+            if island == 0 || island != self.node_island[b as usize] {
+                self.rebuild_component(a, nodes);
+            }
+        }
+        for &node in &lattice.nodes {
+            if self.node_island[node as usize] == 0 {
+                self.rebuild_component(node, nodes);
+            }
+        }
+    }
+
+    /// Stop tracking the `broken` links and split any island they held
+    /// together.
+    ///
+    /// Islands touched by a broken link are woken up.
+    ///
+    /// Links that are not tracked, or that were already handled, are
+    /// ignored.
+    pub fn handle_broken_links(&mut self, broken: &[u32], nodes: &mut NodesRowTable) {
+        for &link in broken {
+            let Some(&LinkNodes(a, b)) = self.link_nodes.get(link as usize) else {
+                continue;
+            };
+            if a == 0 && b == 0 {
+                continue;
+            }
+            self.wake_node(a, nodes);
+
+            self.link_nodes[link as usize] = LinkNodes::default();
+            self.adjacency[a as usize].retain(|&(l, _)| l != link);
+            self.adjacency[b as usize].retain(|&(l, _)| l != link);
+
+            if self.search_split(a, b) {
+                continue;
+            }
+
+            // `queue` now holds the smaller of the two sides, which breaks
+            // off unless it is the only one still anchored
+            let old = self.node_island[a as usize];
+            let new = self.alloc_island();
+
+            let mut component = std::mem::take(&mut self.queue);
+            for &node in &component {
+                self.node_island[node as usize] = new;
+            }
+            self.islands[old as usize]
+                .nodes
+                .retain(|&node| self.node_island[node as usize] == old);
+
+            let island = &mut self.islands[new as usize];
+            island.nodes.append(&mut component);
+            self.queue = component;
+
+            self.refresh_anchored(old);
+            self.refresh_anchored(new);
+
+            if self.islands[new as usize].anchored && !self.islands[old as usize].anchored {
+                self.swap_islands(old, new);
+            }
+            self.frame_splits.push(new);
+        }
+    }
+
+    /// Stop tracking the `removed` nodes and every link attached to them.
+    ///
+    /// This must be called before the nodes are freed from `nodes`. Islands
+    /// the removed nodes were linked to are rebuilt and woken up, but are not
+    /// reported as splits.
+    ///
+    /// Nodes that are not tracked are ignored.
+    pub fn remove_nodes(&mut self, removed: &[u32], nodes: &mut NodesRowTable) {
+        let mut touched_islands = Vec::new();
+        for &node in removed {
+            let Some(id) = self.island_of(node) else {
+                continue;
+            };
+            for (link, other) in std::mem::take(&mut self.adjacency[node as usize]) {
+                self.link_nodes[link as usize] = LinkNodes::default();
+                self.adjacency[other as usize].retain(|&(l, _)| l != link);
+            }
+            self.node_island[node as usize] = 0;
+            self.anchors[node as usize] = false;
+            touched_islands.push(id);
+        }
+        touched_islands.sort_unstable();
+        touched_islands.dedup();
+
+        // the remaining nodes of an island may no longer be connected
+        let mut orphans = Vec::new();
+        for id in touched_islands {
+            self.wake_island(id, nodes);
+            let island = std::mem::take(&mut self.islands[id as usize]);
+            self.free_islands.push(id);
+
+            for node in island.nodes {
+                if self.node_island[node as usize] != 0 {
+                    self.node_island[node as usize] = 0;
+                    orphans.push(node);
+                }
+            }
+        }
+        for node in orphans {
+            if self.node_island[node as usize] == 0 {
+                self.rebuild_component(node, nodes);
+            }
+        }
+    }
+
+    /// Advance the sleep state of every awake island by one update.
+    ///
+    /// Islands that stayed under the `options` thresholds for long enough
+    /// are put to sleep: their velocities are zeroed and their nodes are
+    /// flagged as sleeping in `nodes`.
+    pub fn update_sleep(
+        &mut self,
+        nodes: &mut NodesRowTable,
+        links: &LinksRowTable,
+        options: &SleepOptions,
+    ) {
+        let speed2 = options.speed * options.speed;
+
+        for id in 1..self.islands.len() {
+            let island = &self.islands[id];
+            if !island.is_alive() || island.asleep {
+                continue;
+            }
+
+            let still = island.nodes.iter().all(|&node| {
+                let index = unsafe { nodes.get_indirect_unchecked(node) };
+                if nodes.velocity_slice()[index as usize].length_squared() > speed2 {
+                    return false;
+                }
+
+                self.adjacency[node as usize].iter().all(|&(link, other)| {
+                    let Some(link_index) = links.get_indirect(link) else {
+                        return true;
+                    };
+                    let other_index = unsafe { nodes.get_indirect_unchecked(other) };
+                    let positions = nodes.current_pos_slice();
+                    let dist = positions[index as usize].distance(positions[other_index as usize]);
+                    let rest = links.rest_length_slice()[link_index as usize];
+
+                    (dist - rest).abs() <= options.error
+                })
+            });
+
+            let island = &mut self.islands[id];
+            if !still {
+                island.still_frames = 0;
+                continue;
+            }
+
+            island.still_frames += 1;
+            if island.still_frames >= options.frames {
+                island.asleep = true;
+                for &node in &island.nodes {
+                    let index = unsafe { nodes.get_indirect_unchecked(node) } as usize;
+                    nodes.velocity_mut_slice()[index] = glam::Vec3::ZERO;
+                    nodes.sleeping_mut_slice()[index] = true;
+                }
+            }
+        }
+    }
+
+    /// Wake up the island `node` belongs to, if it is asleep.
+    ///
+    /// This also resets the sleep timer of an awake island.
+    pub fn wake_node(&mut self, node: u32, nodes: &mut NodesRowTable) {
+        if let Some(id) = self.island_of(node) {
+            self.wake_island(id, nodes);
+        }
+    }
+
+    /// Wake up the island `id`, if it is asleep.
+    ///
+    /// This also resets the sleep timer of an awake island.
+    pub fn wake_island(&mut self, id: u32, nodes: &mut NodesRowTable) {
+        let Some(island) = self.islands.get_mut(id as usize) else {
+            return;
+        };
+
+        island.still_frames = 0;
+        if !island.asleep {
+            return;
+        }
+
+        island.asleep = false;
+        for &node in &island.nodes {
+            let index = unsafe { nodes.get_indirect_unchecked(node) };
+            nodes.sleeping_mut_slice()[index as usize] = false;
+        }
+    }
+
+    fn reserve_node(&mut self, node: u32) {
+        let len = node as usize + 1;
+        if self.node_island.len() < len {
+            self.node_island.resize(len, 0);
+            self.anchors.resize(len, false);
+            self.adjacency.resize_with(len, Vec::new);
+            self.visited.resize(len, 0);
+        }
+    }
+
+    fn insert_link(&mut self, link: u32, relation: LinkNodes) {
+        let len = link as usize + 1;
+        if self.link_nodes.len() < len {
+            self.link_nodes.resize(len, LinkNodes::default());
+        }
+        self.link_nodes[link as usize] = relation;
+
+        let LinkNodes(a, b) = relation;
+        self.adjacency[a as usize].push((link, b));
+        self.adjacency[b as usize].push((link, a));
+    }
+
+    /// Assign every node connected to `start` to a single new island,
+    /// replacing any island they previously belonged to.
+    fn rebuild_component(&mut self, start: u32, nodes: &mut NodesRowTable) {
+        self.search(start, None);
+
+        let new = self.alloc_island();
+        let component = std::mem::take(&mut self.queue);
+        for &node in &component {
+            let old = self.node_island[node as usize];
+            if old != 0 && old != new {
+                self.wake_island(old, nodes);
+                self.free_island(old);
+            }
+            self.node_island[node as usize] = new;
+        }
+
+        self.islands[new as usize]
+            .nodes
+            .extend_from_slice(&component);
+        self.queue = component;
+        self.refresh_anchored(new);
+    }
+
+    /// Breadth-first search from `start` over intact links.
+    ///
+    /// Returns `true` as soon as `target` is reached. Otherwise, `queue`
+    /// holds every node reachable from `start` once this returns.
+    fn search(&mut self, start: u32, target: Option<u32>) -> bool {
+        self.visit_stamp = self.visit_stamp.wrapping_add(1);
+        if self.visit_stamp == 0 {
+            self.visited.fill(0);
+            self.visit_stamp = 1;
+        }
+        let stamp = self.visit_stamp;
+
+        self.queue.clear();
+        self.queue.push(start);
+        self.visited[start as usize] = stamp;
+
+        let mut head = 0;
+        while head < self.queue.len() {
+            let node = self.queue[head];
+            head += 1;
+
+            for &(_, other) in &self.adjacency[node as usize] {
+                if Some(other) == target {
+                    return true;
+                }
+                if self.visited[other as usize] != stamp {
+                    self.visited[other as usize] = stamp;
+                    self.queue.push(other);
+                }
+            }
+        }
+        false
+    }
+
+    /// Search from both ends of a broken link at once, one node at a time.
+    ///
+    /// Returns `true` as soon as the searches meet. Otherwise, `queue` holds
+    /// every node of the smaller side once this returns, and only that side
+    /// was walked in full.
+    fn search_split(&mut self, a: u32, b: u32) -> bool {
+        self.visit_stamp = self.visit_stamp.wrapping_add(2);
+        if self.visit_stamp <= 1 {
+            self.visited.fill(0);
+            self.visit_stamp = 2;
+        }
+        let (stamp_a, stamp_b) = (self.visit_stamp - 1, self.visit_stamp);
+
+        self.queue.clear();
+        self.queue.push(a);
+        self.visited[a as usize] = stamp_a;
+        self.back_queue.clear();
+        self.back_queue.push(b);
+        self.visited[b as usize] = stamp_b;
+
+        let (mut head_a, mut head_b) = (0, 0);
+        loop {
+            if head_a == self.queue.len() {
+                return false;
+            }
+            let node = self.queue[head_a];
+            head_a += 1;
+            for &(_, other) in &self.adjacency[node as usize] {
+                match self.visited[other as usize] {
+                    stamp if stamp == stamp_b => return true,
+                    stamp if stamp == stamp_a => {}
+                    _ => {
+                        self.visited[other as usize] = stamp_a;
+                        self.queue.push(other);
+                    }
+                }
+            }
+
+            if head_b == self.back_queue.len() {
+                std::mem::swap(&mut self.queue, &mut self.back_queue);
+                return false;
+            }
+            let node = self.back_queue[head_b];
+            head_b += 1;
+            for &(_, other) in &self.adjacency[node as usize] {
+                match self.visited[other as usize] {
+                    stamp if stamp == stamp_a => return true,
+                    stamp if stamp == stamp_b => {}
+                    _ => {
+                        self.visited[other as usize] = stamp_b;
+                        self.back_queue.push(other);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Exchange the nodes of the islands `a` and `b`, keeping their sleep
+    /// state with their IDs.
+    fn swap_islands(&mut self, a: u32, b: u32) {
+        let nodes_a = std::mem::take(&mut self.islands[a as usize].nodes);
+        let nodes_b = std::mem::replace(&mut self.islands[b as usize].nodes, nodes_a);
+        self.islands[a as usize].nodes = nodes_b;
+
+        for id in [a, b] {
+            for &node in &self.islands[id as usize].nodes {
+                self.node_island[node as usize] = id;
+            }
+            self.refresh_anchored(id);
+        }
+    }
+
+    fn alloc_island(&mut self) -> u32 {
+        if let Some(id) = self.free_islands.pop() {
+            return id;
+        }
+        let id = self.islands.len() as u32;
+        self.islands.push(Island::default());
+        id
+    }
+
+    fn free_island(&mut self, id: u32) {
+        let island = &mut self.islands[id as usize];
+        if island.is_alive() {
+            *island = Island::default();
+            self.free_islands.push(id);
+        }
+    }
+
+    fn refresh_anchored(&mut self, id: u32) {
+        let island = &mut self.islands[id as usize];
+        island.anchored = island.nodes.iter().any(|&node| self.anchors[node as usize]);
+    }
+}
+
+impl Snapshot for Island {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.nodes);
+        writer.write(&self.anchored);
+        writer.write(&self.asleep);
+        writer.write(&self.still_frames);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self {
+            nodes: reader.read()?,
+            anchored: reader.read()?,
+            asleep: reader.read()?,
+            still_frames: reader.read()?,
+        })
+    }
+}
+
+impl Snapshot for SleepOptions {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.speed);
+        writer.write(&self.error);
+        writer.write(&self.frames);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self::new(reader.read()?, reader.read()?, reader.read()?))
+    }
+}
+
+/// The islands and the graph they were computed from.
+///
+/// Splits reported since the last [`IslandTracker::clear_frame_splits`] are
+/// not saved.
+impl Snapshot for IslandTracker {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.islands);
+        writer.write(&self.free_islands);
+        writer.write(&self.node_island);
+        writer.write(&self.anchors);
+        writer.write(&self.adjacency);
+        writer.write(&self.link_nodes);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        let islands = reader.read::<Vec<Island>>()?;
+        let free_islands = reader.read()?;
+        let node_island = reader.read::<Vec<u32>>()?;
+        let anchors = reader.read::<Vec<bool>>()?;
+        let adjacency = reader.read::<Vec<Vec<(u32, u32)>>>()?;
+        let link_nodes = reader.read::<Vec<LinkNodes>>()?;
+
+        if islands.is_empty() || node_island.is_empty() || link_nodes.is_empty() {
+            return Err(SnapshotError::Corrupt("missing degenerate island"));
+        }
+        if anchors.len() != node_island.len() || adjacency.len() != node_island.len() {
+            return Err(SnapshotError::Corrupt("island node maps differ in length"));
+        }
+
+        let node_count = node_island.len() as u32;
+        let island_nodes = islands
+            .iter()
+            .flat_map(|island| island.nodes.iter().copied());
+        let linked_nodes = link_nodes.iter().flat_map(|&LinkNodes(a, b)| [a, b]);
+        let adjacent_nodes = adjacency.iter().flatten().map(|&(_, other)| other);
+        if island_nodes
+            .chain(linked_nodes)
+            .chain(adjacent_nodes)
+            .any(|node| node >= node_count)
+        {
+            return Err(SnapshotError::Corrupt("island node out of range"));
+        }
+
+        let link_count = link_nodes.len() as u32;
+        if adjacency
+            .iter()
+            .flatten()
+            .any(|&(link, _)| link >= link_count)
+        {
+            return Err(SnapshotError::Corrupt("island link out of range"));
+        }
+
+        let island_count = islands.len() as u32;
+        if node_island
+            .iter()
+            .chain(&free_islands)
+            .any(|&id| id >= island_count)
+        {
+            return Err(SnapshotError::Corrupt("island ID out of range"));
+        }
+
+        Ok(Self {
+            islands,
+            free_islands,
+            visited: vec![0; node_island.len()],
+            node_island,
+            anchors,
+            adjacency,
+            link_nodes,
+            frame_splits: Vec::new(),
+            queue: Vec::new(),
+            back_queue: Vec::new(),
+            visit_stamp: 0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xpbd::{XpbdLatticeBuilder, XpbdLinkOptions, XpbdNodeOptions};
+
+    const LINK: XpbdLinkOptions = XpbdLinkOptions::new(1.0);
+
+    fn node(x: f32) -> XpbdNodeOptions {
+        XpbdNodeOptions::new(glam::vec3(x, 0.0, 0.0), 1.0)
+    }
+
+    #[test]
+    fn island_split_on_break() {
+        // A(fixed) - B - C - D
+        let mut builder = XpbdLatticeBuilder::new();
+        let a = builder.node(node(0.0).with_fixed(true));
+        let b = builder.node(node(1.0));
+        let c = builder.node(node(2.0));
+        let d = builder.node(node(3.0));
+        builder.link_nodes(a, b, LINK);
+        let bc = builder.link_nodes(b, c, LINK);
+        builder.link_nodes(c, d, LINK);
+
+        let mut nodes = NodesRowTable::new();
+        let mut links = LinksRowTable::new();
+        let map = builder.export(&mut nodes, &mut links);
+
+        let mut tracker = IslandTracker::new();
+        tracker.insert_lattice(&map, &mut nodes, &links);
+        assert_eq!(tracker.islands().count(), 1);
+
+        let root = tracker.island_of(map.nodes[a as usize]).unwrap();
+        assert!(tracker.island(root).unwrap().is_anchored());
+
+        tracker.handle_broken_links(&[map.links[bc as usize]], &mut nodes);
+        assert_eq!(tracker.islands().count(), 2);
+        assert_eq!(tracker.frame_splits().len(), 1);
+
+        let split = tracker.frame_splits()[0];
+        let island = tracker.island(split).unwrap();
+        assert!(!island.is_anchored());
+
+        let mut split_nodes = island.nodes().to_vec();
+        split_nodes.sort();
+        assert_eq!(
+            split_nodes,
+            vec![map.nodes[c as usize], map.nodes[d as usize]]
+        );
+
+        let root_island = tracker.island(root).unwrap();
+        assert!(root_island.is_anchored());
+        assert_eq!(root_island.nodes().len(), 2);
+    }
+
+    #[test]
+    fn island_split_reports_smaller_side() {
+        // A - B - C - D - E, then A(fixed) - B - C - D - E
+        for fixed in [false, true] {
+            let mut builder = XpbdLatticeBuilder::new();
+            let ids = (0..5)
+                .map(|i| builder.node(node(i as f32).with_fixed(fixed && i == 0)))
+                .collect::<Vec<_>>();
+            let links = ids
+                .windows(2)
+                .map(|pair| builder.link_nodes(pair[0], pair[1], LINK))
+                .collect::<Vec<_>>();
+
+            let mut nodes = NodesRowTable::new();
+            let mut table = LinksRowTable::new();
+            let map = builder.export(&mut nodes, &mut table);
+
+            let mut tracker = IslandTracker::new();
+            tracker.insert_lattice(&map, &mut nodes, &table);
+            let root = tracker.island_of(map.nodes[ids[0] as usize]).unwrap();
+
+            // E breaks off the free chain, A alone holds the anchored one
+            let link = if fixed { links[0] } else { links[3] };
+            tracker.handle_broken_links(&[map.links[link as usize]], &mut nodes);
+            assert_eq!(tracker.frame_splits().len(), 1);
+
+            let split = tracker.island(tracker.frame_splits()[0]).unwrap();
+            assert!(!split.is_anchored());
+            assert_eq!(split.nodes().len(), if fixed { 4 } else { 1 });
+
+            let root_island = tracker.island(root).unwrap();
+            assert_eq!(root_island.is_anchored(), fixed);
+            assert_eq!(root_island.nodes().len(), if fixed { 1 } else { 4 });
+            assert_eq!(tracker.island_of(map.nodes[ids[0] as usize]), Some(root));
+        }
+    }
+
+    #[test]
+    fn island_cycle_holds_on_break() {
+        // A - B - C - A
+        let mut builder = XpbdLatticeBuilder::new();
+        let a = builder.node(node(0.0));
+        let b = builder.node(node(1.0));
+        let c = builder.node(node(2.0));
+        let ab = builder.link_nodes(a, b, LINK);
+        builder.link_nodes(b, c, LINK);
+        builder.link_nodes(c, a, LINK);
+
+        let mut nodes = NodesRowTable::new();
+        let mut links = LinksRowTable::new();
+        let map = builder.export(&mut nodes, &mut links);
+
+        let mut tracker = IslandTracker::new();
+        tracker.insert_lattice(&map, &mut nodes, &links);
+
+        tracker.handle_broken_links(&[map.links[ab as usize]], &mut nodes);
+        // handling the same link twice is a no-op
+        tracker.handle_broken_links(&[map.links[ab as usize]], &mut nodes);
+
+        assert_eq!(tracker.link_count(map.nodes[a as usize]), 1);
+        assert_eq!(tracker.link_count(map.nodes[b as usize]), 1);
+        assert_eq!(tracker.link_count(map.nodes[c as usize]), 2);
+        assert_eq!(tracker.link_count(0), 0);
+
+        assert_eq!(tracker.islands().count(), 1);
+        assert!(tracker.frame_splits().is_empty());
+        assert!(!tracker.island(1).unwrap().is_anchored());
+    }
+
+    #[test]
+    fn island_remove_nodes() {
+        // A(fixed) - B - C - D
+        let mut builder = XpbdLatticeBuilder::new();
+        let a = builder.node(node(0.0).with_fixed(true));
+        let b = builder.node(node(1.0));
+        let c = builder.node(node(2.0));
+        let d = builder.node(node(3.0));
+        builder.link_nodes(a, b, LINK);
+        builder.link_nodes(b, c, LINK);
+        builder.link_nodes(c, d, LINK);
+
+        let mut nodes = NodesRowTable::new();
+        let mut links = LinksRowTable::new();
+        let map = builder.export(&mut nodes, &mut links);
+
+        let mut tracker = IslandTracker::new();
+        tracker.insert_lattice(&map, &mut nodes, &links);
+
+        tracker.remove_nodes(&[map.nodes[b as usize]], &mut nodes);
+        assert_eq!(tracker.islands().count(), 2);
+        assert!(tracker.frame_splits().is_empty());
+        assert_eq!(tracker.island_of(map.nodes[b as usize]), None);
+
+        let root = tracker.island_of(map.nodes[a as usize]).unwrap();
+        assert!(tracker.island(root).unwrap().is_anchored());
+        assert_eq!(tracker.island(root).unwrap().nodes().len(), 1);
+
+        let rest = tracker.island_of(map.nodes[c as usize]).unwrap();
+        assert_eq!(tracker.island_of(map.nodes[d as usize]), Some(rest));
+        assert!(!tracker.island(rest).unwrap().is_anchored());
+
+        // breaking the links of a removed node is a no-op
+        tracker.handle_broken_links(&map.links[..2], &mut nodes);
+        assert_eq!(tracker.islands().count(), 2);
+
+        tracker.remove_nodes(&map.nodes, &mut nodes);
+        assert_eq!(tracker.islands().count(), 0);
+    }
+
+    #[test]
+    fn island_merge_through_external_nodes() {
+        let mut left = XpbdLatticeBuilder::new();
+        left.node(node(0.0));
+        left.node(node(1.0));
+        left.link(LINK);
+
+        let right = left.clone();
+
+        let mut nodes = NodesRowTable::new();
+        let mut links = LinksRowTable::new();
+        let left = left.export(&mut nodes, &mut links);
+        let right = right.export(&mut nodes, &mut links);
+
+        let mut tracker = IslandTracker::new();
+        tracker.insert_lattice(&left, &mut nodes, &links);
+        tracker.insert_lattice(&right, &mut nodes, &links);
+        assert_eq!(tracker.islands().count(), 2);
+
+        // a brace made only of links between live nodes
+        let mut brace = XpbdLatticeBuilder::new();
+        brace.external(left.nodes[1], &nodes);
+        brace.external(right.nodes[0], &nodes);
+        brace.link(LINK);
+        let brace = brace.export(&mut nodes, &mut links);
+        assert!(brace.owned_nodes().next().is_none());
+
+        tracker.insert_lattice(&brace, &mut nodes, &links);
+        assert_eq!(tracker.islands().count(), 1);
+        assert_eq!(
+            tracker.island_of(left.nodes[0]),
+            tracker.island_of(right.nodes[1])
+        );
+    }
+
+    #[test]
+    fn island_sleep_and_wake() {
+        let mut builder = XpbdLatticeBuilder::new();
+        let a = builder.node(node(0.0));
+        let b = builder.node(node(1.0));
+        builder.link_nodes(a, b, LINK);
+
+        let mut nodes = NodesRowTable::new();
+        let mut links = LinksRowTable::new();
+        let map = builder.export(&mut nodes, &mut links);
+
+        let mut tracker = IslandTracker::new();
+        tracker.insert_lattice(&map, &mut nodes, &links);
+
+        const OPTIONS: SleepOptions = SleepOptions::new(0.1, 0.01, 3);
+        for _ in 0..OPTIONS.frames {
+            assert!(!tracker.island(1).unwrap().is_asleep());
+            tracker.update_sleep(&mut nodes, &links, &OPTIONS);
+        }
+        assert!(tracker.island(1).unwrap().is_asleep());
+        assert!(nodes.sleeping_slice()[1..].iter().all(|&sleeping| sleeping));
+
+        tracker.wake_node(map.nodes[b as usize], &mut nodes);
+        assert!(!tracker.island(1).unwrap().is_asleep());
+        assert!(
+            nodes.sleeping_slice()[1..]
+                .iter()
+                .all(|&sleeping| !sleeping)
+        );
+    }
+}
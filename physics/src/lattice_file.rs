@@ -0,0 +1,492 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+use toml::Spanned;
+
+use crate::{
+    material::Surface,
+    snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter},
+    xpbd::{
+        LatticeError, LinkLimits, Plasticity, XpbdLatticeBuilder, XpbdLinkOptions, XpbdNodeOptions,
+    },
+};
+
+/// Format version of the binary lattice files written by
+/// [`XpbdLatticeBuilder::to_bytes`].
+pub const LATTICE_FILE_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LatticeFileError {
+    /// The text is not valid TOML, or does not describe a lattice.
+    Parse { line: usize, message: String },
+    /// A link or group refers to a node that is not defined.
+    UnknownNode { line: usize, node: u32 },
+    /// A group refers to a link that is not defined.
+    UnknownLink { line: usize, link: u32 },
+    /// The lattice is defined, but not valid; such as a self-link.
+    Invalid { line: usize, error: LatticeError },
+}
+
+impl LatticeFileError {
+    fn at(line: usize, error: LatticeError) -> Self {
+        match error {
+            LatticeError::UnknownNode(node) => Self::UnknownNode { line, node },
+            LatticeError::UnknownLink(link) => Self::UnknownLink { line, link },
+            error => Self::Invalid { line, error },
+        }
+    }
+
+    /// The line of the file the error was found on, starting at `1`.
+    pub fn line(&self) -> usize {
+        match *self {
+            Self::Parse { line, .. } | Self::UnknownNode { line, .. } => line,
+            Self::UnknownLink { line, .. } | Self::Invalid { line, .. } => line,
+        }
+    }
+}
+
+impl std::fmt::Display for LatticeFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Parse { line, message } => write!(f, "line {line}: {message}"),
+            Self::UnknownNode { line, node } => write!(f, "line {line}: unknown node {node}"),
+            Self::UnknownLink { line, link } => write!(f, "line {line}: unknown link {link}"),
+            Self::Invalid { line, error } => write!(f, "line {line}: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for LatticeFileError {}
+
+/// Lattice files come in two formats holding the same data:
+/// * A human-editable TOML format; see [`XpbdLatticeBuilder::from_toml`].
+/// * A compact binary format; see [`XpbdLatticeBuilder::from_bytes`].
+impl XpbdLatticeBuilder {
+    /// Load a lattice from its TOML representation.
+    ///
+    /// ```toml
+    /// [[node]]
+    /// pos = [0.0, 0.0, 0.0]
+    /// mass = 10.0
+    /// fixed = true
+    ///
+    /// [[node]]
+    /// pos = [0.0, 2.0, 0.0]
+    /// mass = 10.0
+    ///
+    /// [[link]]
+    /// nodes = [0, 1]
+    /// compliance = 1e-6
+    /// rest_length = 2.0
+    ///
+    /// [group.anchors]
+    /// nodes = [0]
+    /// ```
+    ///
+    /// Nodes and links are identified by their position in the file,
+    /// starting at `0`. Besides the keys above, nodes accept `restitution`
+    /// and `friction`, and links accept `tension`, `compression` and a
+    /// `plasticity` table.
+    ///
+    /// # Errors
+    /// Will return an error, with the line it was found on, if `text` is not
+    /// a valid lattice.
+    pub fn from_toml(text: &str) -> Result<Self, LatticeFileError> {
+        let doc = toml::from_str::<LatticeDoc>(text).map_err(|e| LatticeFileError::Parse {
+            line: e.span().map_or(1, |span| line_of(text, span.start)),
+            message: e.message().to_owned(),
+        })?;
+
+        let mut builder = Self::with_capacity(doc.node.len());
+        for node in &doc.node {
+            builder.node(node.get_ref().options());
+        }
+
+        for link in &doc.link {
+            let line = line_of(text, link.span().start);
+            let DocLink { nodes: [a, b], .. } = *link.get_ref();
+            builder
+                .try_link_nodes(a, b, link.get_ref().options())
+                .map_err(|error| LatticeFileError::at(line, error))?;
+        }
+
+        for (name, group) in &doc.group {
+            let line = line_of(text, group.span().start);
+            let group = group.get_ref();
+            builder
+                .try_tag_nodes(name, &group.nodes)
+                .and_then(|_| builder.try_tag_links(name, &group.links))
+                .map_err(|error| LatticeFileError::at(line, error))?;
+        }
+
+        Ok(builder)
+    }
+
+    /// Save the lattice in its TOML representation.
+    ///
+    /// See [`XpbdLatticeBuilder::from_toml`] for the format. External nodes
+    /// are saved as regular nodes, as their handles are only meaningful to
+    /// the tables they are live in.
+    pub fn to_toml(&self) -> String {
+        let doc = LatticeDoc {
+            node: self
+                .nodes()
+                .iter()
+                .map(|node| unspanned(DocNode::from(node)))
+                .collect(),
+            link: self
+                .links()
+                .map(|(a, b, options)| unspanned(DocLink::new(a, b, options)))
+                .collect(),
+            group: self
+                .groups()
+                .iter()
+                .map(|(name, group)| {
+                    let doc_group = DocGroup {
+                        nodes: group.nodes.clone(),
+                        links: group.links.clone(),
+                    };
+                    (name.clone(), unspanned(doc_group))
+                })
+                .collect(),
+        };
+        toml::to_string(&doc).expect("lattice documents are always valid toml")
+    }
+
+    /// Load a lattice from its binary representation.
+    ///
+    /// # Errors
+    /// Will return an error if `bytes` is not a lattice file of the current
+    /// [`LATTICE_FILE_VERSION`], or if it is truncated or corrupt.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let mut reader = SnapshotReader::new(bytes)?;
+        if reader.version() != LATTICE_FILE_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(reader.version()));
+        }
+        let builder = reader.read::<Self>()?;
+        if !reader.is_empty() {
+            return Err(SnapshotError::Corrupt("trailing bytes"));
+        }
+        Ok(builder)
+    }
+
+    /// Save the lattice in its binary representation.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = SnapshotWriter::new(LATTICE_FILE_VERSION);
+        writer.write(self);
+        writer.finish()
+    }
+}
+
+/// The nodes, links and groups of the lattice; the stack is not saved.
+///
+/// External nodes are saved as regular nodes.
+impl Snapshot for XpbdLatticeBuilder {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write_slice(self.nodes());
+        writer.write(&(self.links().len() as u32));
+        for (a, b, options) in self.links() {
+            writer.write(&a);
+            writer.write(&b);
+            writer.write(options);
+        }
+        writer.write(&(self.groups().len() as u32));
+        for (name, group) in self.groups() {
+            writer.write(name);
+            writer.write(&group.nodes);
+            writer.write(&group.links);
+        }
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        let nodes = reader.read_vec::<XpbdNodeOptions>()?;
+        let mut builder = Self::with_capacity(nodes.len());
+        for node in nodes {
+            builder.node(node);
+        }
+
+        let link_count = reader.read::<u32>()?;
+        for _ in 0..link_count {
+            let a = reader.read::<u32>()?;
+            let b = reader.read::<u32>()?;
+            builder
+                .try_link_nodes(a, b, reader.read()?)
+                .map_err(|_| SnapshotError::Corrupt("invalid link"))?;
+        }
+
+        let group_count = reader.read::<u32>()?;
+        for _ in 0..group_count {
+            let name = reader.read::<String>()?;
+            let nodes = reader.read::<Vec<u32>>()?;
+            let links = reader.read::<Vec<u32>>()?;
+            builder
+                .try_tag_nodes(&name, &nodes)
+                .and_then(|_| builder.try_tag_links(&name, &links))
+                .map_err(|_| SnapshotError::Corrupt("group with unknown member"))?;
+        }
+
+        Ok(builder)
+    }
+}
+
+fn line_of(text: &str, offset: usize) -> usize {
+    text.as_bytes()[..offset.min(text.len())]
+        .iter()
+        .filter(|&&b| b == b'\n')
+        .count()
+        + 1
+}
+
+fn unspanned<T>(value: T) -> Spanned<T> {
+    Spanned::new(0..0, value)
+}
+
+// serde representation of the toml format
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LatticeDoc {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    node: Vec<Spanned<DocNode>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    link: Vec<Spanned<DocLink>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    group: BTreeMap<String, Spanned<DocGroup>>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DocNode {
+    pos: [f32; 3],
+    mass: f32,
+    #[serde(default, skip_serializing_if = "is_false")]
+    fixed: bool,
+    #[serde(
+        default = "default_restitution",
+        skip_serializing_if = "is_default_restitution"
+    )]
+    restitution: f32,
+    #[serde(
+        default = "default_friction",
+        skip_serializing_if = "is_default_friction"
+    )]
+    friction: f32,
+}
+
+impl DocNode {
+    fn options(&self) -> XpbdNodeOptions {
+        XpbdNodeOptions::new(glam::Vec3::from_array(self.pos), self.mass)
+            .with_fixed(self.fixed)
+            .with_surface(Surface::new(self.restitution, self.friction))
+    }
+}
+
+impl From<&XpbdNodeOptions> for DocNode {
+    fn from(node: &XpbdNodeOptions) -> Self {
+        Self {
+            pos: node.pos().to_array(),
+            mass: node.mass(),
+            fixed: node.is_fixed(),
+            restitution: node.surface().restitution,
+            friction: node.surface().friction,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DocLink {
+    nodes: [u32; 2],
+    compliance: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    rest_length: Option<f32>,
+    #[serde(
+        default = "default_tension",
+        skip_serializing_if = "is_default_tension"
+    )]
+    tension: f32,
+    #[serde(
+        default = "default_compression",
+        skip_serializing_if = "is_default_compression"
+    )]
+    compression: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    plasticity: Option<DocPlasticity>,
+}
+
+impl DocLink {
+    fn new(a: u32, b: u32, options: &XpbdLinkOptions) -> Self {
+        let plasticity = options.plasticity();
+        Self {
+            nodes: [a, b],
+            compliance: options.compliance(),
+            rest_length: options.rest_length(),
+            tension: options.limits().tension,
+            compression: options.limits().compression,
+            plasticity: (!plasticity.is_elastic()).then_some(DocPlasticity {
+                yield_force: plasticity.yield_force,
+                creep: plasticity.creep,
+                hardening: plasticity.hardening,
+                ultimate_strain: plasticity.ultimate_strain,
+            }),
+        }
+    }
+
+    fn options(&self) -> XpbdLinkOptions {
+        let mut options =
+            XpbdLinkOptions::new(self.compliance).and_limits(self.tension, self.compression);
+        if let Some(rest_length) = self.rest_length {
+            options = options.and_rest_length(rest_length);
+        }
+        if let Some(p) = &self.plasticity {
+            options = options.and_plasticity(Plasticity::new(
+                p.yield_force,
+                p.creep,
+                p.hardening,
+                p.ultimate_strain,
+            ));
+        }
+        options
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DocPlasticity {
+    yield_force: f32,
+    creep: f32,
+    hardening: f32,
+    ultimate_strain: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DocGroup {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    nodes: Vec<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    links: Vec<u32>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+fn default_restitution() -> f32 {
+    Surface::DEFAULT.restitution
+}
+
+fn is_default_restitution(value: &f32) -> bool {
+    *value == Surface::DEFAULT.restitution
+}
+
+fn default_friction() -> f32 {
+    Surface::DEFAULT.friction
+}
+
+fn is_default_friction(value: &f32) -> bool {
+    *value == Surface::DEFAULT.friction
+}
+
+fn default_tension() -> f32 {
+    LinkLimits::DEFAULT.tension
+}
+
+fn is_default_tension(value: &f32) -> bool {
+    *value == LinkLimits::DEFAULT.tension
+}
+
+fn default_compression() -> f32 {
+    LinkLimits::DEFAULT.compression
+}
+
+fn is_default_compression(value: &f32) -> bool {
+    *value == LinkLimits::DEFAULT.compression
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOWER: &str = r#"
+[[node]]
+pos = [0.0, 0.0, 0.0]
+mass = 10.0
+fixed = true
+
+[[node]]
+pos = [0.0, 2.0, 0.0]
+mass = 10.0
+friction = 0.5
+
+[[node]]
+pos = [1.0, 2.0, 0.0]
+mass = 5.0
+
+[[link]]
+nodes = [0, 1]
+compliance = 1e-6
+
+[[link]]
+nodes = [1, 2]
+compliance = 1e-4
+rest_length = 1.5
+tension = 100.0
+
+[[link]]
+nodes = [0, 2]
+compliance = 1e-6
+plasticity = { yield_force = 10.0, creep = 0.1, hardening = 2.0, ultimate_strain = 0.2 }
+
+[group.anchors]
+nodes = [0]
+
+[group."floor:1"]
+nodes = [1, 2]
+links = [1]
+"#;
+
+    #[test]
+    fn lattice_file_toml_round_trip() {
+        let builder = XpbdLatticeBuilder::from_toml(TOWER).unwrap();
+        assert_eq!(builder.nodes().len(), 3);
+        assert_eq!(builder.links().len(), 3);
+        assert!(builder.nodes()[0].is_fixed());
+        assert_eq!(builder.nodes()[1].surface().friction, 0.5);
+
+        let (a, b, link) = builder.links().nth(1).unwrap();
+        assert_eq!((a, b), (1, 2));
+        assert_eq!(link.rest_length(), Some(1.5));
+        assert_eq!(
+            link.limits(),
+            LinkLimits::new(100.0, LinkLimits::DEFAULT.compression)
+        );
+
+        assert_eq!(builder.groups().len(), 2);
+        assert_eq!(builder.group("floor:1").unwrap().links, [1]);
+
+        let text = builder.to_toml();
+        let reloaded = XpbdLatticeBuilder::from_toml(&text).unwrap();
+        assert_eq!(reloaded.to_toml(), text);
+        assert_eq!(reloaded.groups(), builder.groups());
+    }
+
+    #[test]
+    fn lattice_file_binary_round_trip() {
+        let builder = XpbdLatticeBuilder::from_toml(TOWER).unwrap();
+        let reloaded = XpbdLatticeBuilder::from_bytes(&builder.to_bytes()).unwrap();
+        assert_eq!(reloaded.to_toml(), builder.to_toml());
+    }
+
+    #[test]
+    fn lattice_file_error_lines() {
+        let text = "[[node]]\npos = [0.0, 0.0, 0.0]\nmass = 1.0\n\n[[link]]\nnodes = [0, 3]\ncompliance = 0.0\n";
+        assert_eq!(
+            XpbdLatticeBuilder::from_toml(text).unwrap_err(),
+            LatticeFileError::UnknownNode { line: 5, node: 3 }
+        );
+
+        let text = "[[node]]\npos = [0.0, 0.0]\nmass = 1.0\n";
+        assert_eq!(XpbdLatticeBuilder::from_toml(text).unwrap_err().line(), 2);
+    }
+}
@@ -1,5 +1,13 @@
+pub mod colouring;
+pub mod island;
+pub mod lattice_file;
+pub mod material;
+pub mod shape_match;
+pub mod snapshot;
 pub mod xpbd;
 
+use crate::snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter};
+
 #[derive(Clone, Copy, Debug, Default, PartialEq)]
 pub struct Line {
     pub dir: glam::Vec3,
@@ -138,6 +146,9 @@ impl From<ethel::state::camera::ViewPoint> for Ray {
     }
 }
 
+/// Advance a body with no constraints by `delta`, with semi-implicit Euler.
+///
+/// `forces` accelerate the body by `inv_mass` before it moves.
 pub fn integrate_bare_body(
     position: &mut glam::Vec3,
     velocity: &mut glam::Vec3,
@@ -145,6 +156,213 @@ pub fn integrate_bare_body(
     forces: glam::Vec3,
     delta: janus::context::DeltaTime,
 ) {
-    let t = delta.as_f32();
-    *position += *velocity * t + forces * inv_mass * t * t;
+    integrate_bare_body_seconds(position, velocity, inv_mass, forces, delta.as_f32());
+}
+
+/// Advance a body with no constraints by `seconds`.
+///
+/// See [`integrate_bare_body`].
+pub fn integrate_bare_body_seconds(
+    position: &mut glam::Vec3,
+    velocity: &mut glam::Vec3,
+    inv_mass: f32,
+    forces: glam::Vec3,
+    seconds: f32,
+) {
+    *velocity += forces * inv_mass * seconds;
+    *position += *velocity * seconds;
+}
+
+/// A rigid body, moving and spinning as a whole.
+///
+/// `position` is the centre of mass, and `inv_inertia` the inverse inertia
+/// tensor around it in body space; `orientation` turns body space into world
+/// space.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RigidBody {
+    pub position: glam::Vec3,
+    pub orientation: glam::Quat,
+    pub velocity: glam::Vec3,
+    pub angular_velocity: glam::Vec3,
+    pub inv_mass: f32,
+    pub inv_inertia: glam::Mat3,
+}
+
+impl RigidBody {
+    /// Build the body made of solid cubes, each given by its centre and
+    /// mass, all sharing `half_extent` and aligned with world space.
+    ///
+    /// The body starts at rest, with the identity orientation: body space is
+    /// world space around the centre of mass. Cubes with no mass are left
+    /// out.
+    ///
+    /// # Returns
+    /// `None` if the cubes have no mass.
+    pub fn from_cubes(cubes: &[(glam::Vec3, f32)], half_extent: f32) -> Option<Self> {
+        let mass = cubes.iter().map(|&(_, m)| m).sum::<f32>();
+        if mass <= 0.0 {
+            return None;
+        }
+        let com = cubes.iter().map(|&(p, m)| p * m).sum::<glam::Vec3>() / mass;
+
+        // each cube around its own centre, moved to the centre of mass
+        let cube = 2.0 / 3.0 * half_extent * half_extent;
+        let mut inertia = glam::Mat3::ZERO;
+        for &(p, m) in cubes {
+            let r = p - com;
+            let offset = glam::Mat3::from_diagonal(glam::Vec3::splat(r.length_squared()))
+                - glam::Mat3::from_cols(r * r.x, r * r.y, r * r.z);
+            inertia += (offset + glam::Mat3::from_diagonal(glam::Vec3::splat(cube))) * m;
+        }
+
+        Some(Self {
+            position: com,
+            orientation: glam::Quat::IDENTITY,
+            velocity: glam::Vec3::ZERO,
+            angular_velocity: glam::Vec3::ZERO,
+            inv_mass: 1.0 / mass,
+            inv_inertia: inertia.inverse(),
+        })
+    }
+
+    /// The inverse inertia tensor in world space.
+    #[inline]
+    pub fn world_inv_inertia(&self) -> glam::Mat3 {
+        let r = glam::Mat3::from_quat(self.orientation);
+        r * self.inv_inertia * r.transpose()
+    }
+
+    /// The world position of the body space point `local`.
+    #[inline]
+    pub fn to_world(&self, local: glam::Vec3) -> glam::Vec3 {
+        self.position + self.orientation * local
+    }
+
+    /// The body space position of the world point `world`.
+    #[inline]
+    pub fn to_local(&self, world: glam::Vec3) -> glam::Vec3 {
+        self.orientation.inverse() * (world - self.position)
+    }
+
+    /// The velocity of the body at the world point `point`.
+    #[inline]
+    pub fn velocity_at(&self, point: glam::Vec3) -> glam::Vec3 {
+        self.velocity + self.angular_velocity.cross(point - self.position)
+    }
+
+    /// Apply `impulse` at the world point `point`.
+    pub fn apply_impulse(&mut self, impulse: glam::Vec3, point: glam::Vec3) {
+        let arm = point - self.position;
+        self.velocity += impulse * self.inv_mass;
+        self.angular_velocity += self.world_inv_inertia() * arm.cross(impulse);
+    }
+
+    /// The inverse of the mass the body opposes to an impulse along the unit
+    /// `direction` at the world point `point`.
+    pub fn inv_effective_mass(&self, direction: glam::Vec3, point: glam::Vec3) -> f32 {
+        let arm = point - self.position;
+        let angular = (self.world_inv_inertia() * arm.cross(direction)).cross(arm);
+        self.inv_mass + direction.dot(angular)
+    }
+
+    /// Advance the body by `delta`, like [`integrate_bare_body`], also
+    /// spinning it by `torque`.
+    ///
+    /// Gyroscopic effects are ignored.
+    pub fn integrate(
+        &mut self,
+        forces: glam::Vec3,
+        torque: glam::Vec3,
+        delta: janus::context::DeltaTime,
+    ) {
+        self.integrate_seconds(forces, torque, delta.as_f32());
+    }
+
+    /// Advance the body by `seconds`.
+    ///
+    /// See [`RigidBody::integrate`].
+    pub fn integrate_seconds(&mut self, forces: glam::Vec3, torque: glam::Vec3, seconds: f32) {
+        integrate_bare_body_seconds(
+            &mut self.position,
+            &mut self.velocity,
+            self.inv_mass,
+            forces,
+            seconds,
+        );
+
+        self.angular_velocity += self.world_inv_inertia() * torque * seconds;
+        let spin = glam::Quat::from_vec4(self.angular_velocity.extend(0.0) * 0.5 * seconds);
+        self.orientation = (self.orientation + spin * self.orientation).normalize();
+    }
+}
+
+impl Snapshot for RigidBody {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.position);
+        writer.write(&self.orientation);
+        writer.write(&self.velocity);
+        writer.write(&self.angular_velocity);
+        writer.write(&self.inv_mass);
+        writer.write(&self.inv_inertia);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self {
+            position: reader.read()?,
+            orientation: reader.read()?,
+            velocity: reader.read()?,
+            angular_velocity: reader.read()?,
+            inv_mass: reader.read()?,
+            inv_inertia: reader.read()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rigid_body_from_cubes() {
+        let cubes = [
+            (glam::vec3(-1.0, 0.0, 0.0), 1.0),
+            (glam::vec3(1.0, 0.0, 0.0), 1.0),
+            (glam::vec3(2.0, 0.0, 0.0), 0.0),
+        ];
+        let body = RigidBody::from_cubes(&cubes, 0.5).unwrap();
+        assert_eq!(body.position, glam::Vec3::ZERO);
+        assert_eq!(body.inv_mass, 0.5);
+
+        // a bar along x spins most easily around x
+        let inertia = body.inv_inertia.inverse();
+        assert!((inertia.x_axis.x - 1.0 / 3.0).abs() < 1e-5);
+        assert!((inertia.y_axis.y - (2.0 + 1.0 / 3.0)).abs() < 1e-5);
+        assert_eq!(inertia.y_axis.y, inertia.z_axis.z);
+
+        assert_eq!(RigidBody::from_cubes(&[], 0.5), None);
+        assert_eq!(RigidBody::from_cubes(&cubes[2..], 0.5), None);
+    }
+
+    #[test]
+    fn rigid_body_impulse() {
+        let cubes = [
+            (glam::vec3(-1.0, 0.0, 0.0), 1.0),
+            (glam::vec3(1.0, 0.0, 0.0), 1.0),
+        ];
+        let mut body = RigidBody::from_cubes(&cubes, 0.5).unwrap();
+
+        // through the centre of mass, the body does not spin
+        body.apply_impulse(glam::Vec3::Y, body.position);
+        assert_eq!(body.velocity, glam::vec3(0.0, 0.5, 0.0));
+        assert_eq!(body.angular_velocity, glam::Vec3::ZERO);
+
+        // off centre, it does, and the impulse fully stops the point it hits
+        let mut body = RigidBody::from_cubes(&cubes, 0.5).unwrap();
+        let point = glam::vec3(1.0, 0.0, 0.0);
+        body.velocity = glam::Vec3::NEG_Y;
+        let impulse = glam::Vec3::Y / body.inv_effective_mass(glam::Vec3::Y, point);
+        body.apply_impulse(impulse, point);
+        assert!(body.velocity_at(point).length() < 1e-5);
+        assert!(body.angular_velocity.z > 0.0);
+    }
 }
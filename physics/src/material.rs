@@ -0,0 +1,172 @@
+use crate::{
+    snapshot::{Snapshot, SnapshotError, SnapshotReader, SnapshotWriter},
+    xpbd::{LinkLimits, Plasticity},
+};
+
+/// Physical properties of the matter nodes and links of a lattice are made
+/// of.
+///
+/// A [`Material`] is resolved into per-node and per-link properties when a
+/// lattice is exported; see [`XpbdNodeOptions::with_material`] and
+/// [`XpbdLinkOptions::from_material`].
+///
+/// [`XpbdNodeOptions::with_material`]: crate::xpbd::XpbdNodeOptions::with_material
+/// [`XpbdLinkOptions::from_material`]: crate::xpbd::XpbdLinkOptions::from_material
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Material {
+    /// Inverse stiffness of links made of this material.
+    pub compliance: f32,
+    /// Force magnitude at which a stretched link breaks.
+    pub tension_strength: f32,
+    /// Force magnitude at which a compressed link breaks.
+    pub compression_strength: f32,
+    /// Mass per unit of volume.
+    pub density: f32,
+    /// Fraction of the normal velocity kept on contact.
+    pub restitution: f32,
+    /// Fraction of the tangential velocity lost on contact.
+    pub friction: f32,
+    /// Permanent deformation of links made of this material.
+    pub plasticity: Plasticity,
+}
+
+impl Material {
+    pub const STEEL: Self = Self::new(0.1e-6, 60_000.0, 80_000.0, 7850.0, 0.3, 0.6)
+        .with_plasticity(Plasticity::new(40_000.0, 0.2, 20_000.0, 0.15));
+    pub const CONCRETE: Self = Self::new(0.2e-5, 20_000.0, 52_000.0, 2400.0, 0.2, 0.8);
+    pub const TIMBER: Self = Self::new(0.1e-3, 8_000.0, 20_000.0, 600.0, 0.4, 0.7);
+    pub const MASONRY: Self = Self::new(0.5e-5, 6_000.0, 40_000.0, 1900.0, 0.2, 0.85);
+    pub const GLASS: Self = Self::new(0.5e-6, 3_000.0, 10_000.0, 2500.0, 0.1, 0.3);
+
+    /// Define a new, purely elastic material.
+    ///
+    /// Use this for materials that are not covered by the presets.
+    pub const fn new(
+        compliance: f32,
+        tension_strength: f32,
+        compression_strength: f32,
+        density: f32,
+        restitution: f32,
+        friction: f32,
+    ) -> Self {
+        Self {
+            compliance,
+            tension_strength,
+            compression_strength,
+            density,
+            restitution,
+            friction,
+            plasticity: Plasticity::ELASTIC,
+        }
+    }
+
+    pub const fn with_plasticity(self, plasticity: Plasticity) -> Self {
+        Self {
+            compliance: self.compliance,
+            tension_strength: self.tension_strength,
+            compression_strength: self.compression_strength,
+            density: self.density,
+            restitution: self.restitution,
+            friction: self.friction,
+            plasticity,
+        }
+    }
+
+    /// The breaking limits of links made of this material.
+    pub const fn limits(&self) -> LinkLimits {
+        LinkLimits::new(self.tension_strength, self.compression_strength)
+    }
+
+    /// The contact response of nodes made of this material.
+    pub const fn surface(&self) -> Surface {
+        Surface::new(self.restitution, self.friction)
+    }
+
+    /// The mass of `volume` units of this material.
+    pub const fn mass_of(&self, volume: f32) -> f32 {
+        self.density * volume
+    }
+}
+
+pub const DEFAULT_RESTITUTION: f32 = 0.4;
+pub const DEFAULT_FRICTION: f32 = 0.8;
+
+/// Contact response of a node.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Surface {
+    pub restitution: f32,
+    pub friction: f32,
+}
+
+impl Surface {
+    pub const DEFAULT: Self = Self::new(DEFAULT_RESTITUTION, DEFAULT_FRICTION);
+
+    pub const fn new(restitution: f32, friction: f32) -> Self {
+        Self {
+            restitution,
+            friction,
+        }
+    }
+}
+
+impl Default for Surface {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl Snapshot for Surface {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.restitution);
+        writer.write(&self.friction);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self::new(reader.read()?, reader.read()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xpbd::{
+        LinksRowTable, NodesRowTable, XpbdLatticeBuilder, XpbdLinkOptions, XpbdNodeOptions,
+    };
+    use ethel::state::data::Column;
+
+    #[test]
+    fn material_resolves_into_options() {
+        const MATERIAL: Material = Material::STEEL;
+
+        let mut builder = XpbdLatticeBuilder::new();
+        let a = builder.node(XpbdNodeOptions::from_material(
+            glam::Vec3::ZERO,
+            0.5,
+            &MATERIAL,
+        ));
+        let b =
+            builder.node(XpbdNodeOptions::new(glam::Vec3::X, 1.0).with_material(&Material::GLASS));
+        builder.link_nodes(a, b, XpbdLinkOptions::from_material(&MATERIAL));
+
+        let mut nodes = NodesRowTable::new();
+        let mut links = LinksRowTable::new();
+        let map = builder.export(&mut nodes, &mut links);
+
+        let a = nodes.get_indirect(map.nodes[0]).unwrap() as usize;
+        assert_eq!(nodes.mass_slice()[a], 7850.0 * 0.5);
+        assert_eq!(nodes.surface_slice()[a], Surface::new(0.3, 0.6));
+
+        // only the contact response is taken from the material
+        let b = nodes.get_indirect(map.nodes[1]).unwrap() as usize;
+        assert_eq!(nodes.mass_slice()[b], 1.0);
+        assert_eq!(nodes.surface_slice()[b], Material::GLASS.surface());
+
+        let link = links.get_indirect(map.links[0]).unwrap() as usize;
+        assert_eq!(links.compliance_slice()[link], 0.1e-6);
+        assert_eq!(
+            links.limits_slice()[link],
+            LinkLimits::new(60_000.0, 80_000.0)
+        );
+        assert_eq!(links.plasticity_slice()[link], MATERIAL.plasticity);
+    }
+}
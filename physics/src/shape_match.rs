@@ -0,0 +1,133 @@
+/// Default iteration cap of [`ShapeMatch::rotation`].
+pub const DEFAULT_MATCH_ITERATIONS: u32 = 16;
+
+const CONVERGED: f32 = 1e-6;
+
+/// Least-squares best-fit rotation between weighted pairs of rest and current
+/// offsets.
+///
+/// The rotation is the rotational part of the polar decomposition of the
+/// weighted covariance of the pairs, found iteratively from a guess as in
+/// Müller et al., "A Robust Method to Extract the Rotational Part of
+/// Deformations". Unlike the SVD used by Kabsch, this stays defined when the
+/// offsets do not span 3D space: of all best-fit rotations, the one closest to
+/// the guess is found. With no pairs at all, the guess is kept.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ShapeMatch {
+    covariance: glam::Mat3,
+    weight: f32,
+}
+
+impl Default for ShapeMatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ShapeMatch {
+    pub const fn new() -> Self {
+        Self {
+            covariance: glam::Mat3::ZERO,
+            weight: 0.0,
+        }
+    }
+
+    /// Add a pair of offsets: `rest` should be rotated onto `current` with
+    /// the given `weight`.
+    pub fn add(&mut self, rest: glam::Vec3, current: glam::Vec3, weight: f32) {
+        let current = current * weight;
+        self.covariance +=
+            glam::Mat3::from_cols(current * rest.x, current * rest.y, current * rest.z);
+        self.weight += weight;
+    }
+
+    /// Total weight of the pairs added so far.
+    #[inline]
+    pub fn weight(&self) -> f32 {
+        self.weight
+    }
+
+    /// Find the best-fit rotation, starting from `guess`.
+    ///
+    /// Stops after `iterations`, or earlier once converged. A warm `guess`,
+    /// such as the last rotation found, converges in very few iterations.
+    pub fn rotation(&self, guess: glam::Quat, iterations: u32) -> glam::Quat {
+        let a = self.covariance;
+        let mut q = guess.normalize();
+
+        for _ in 0..iterations {
+            let r = glam::Mat3::from_quat(q);
+            let torque =
+                r.x_axis.cross(a.x_axis) + r.y_axis.cross(a.y_axis) + r.z_axis.cross(a.z_axis);
+            let alignment =
+                r.x_axis.dot(a.x_axis) + r.y_axis.dot(a.y_axis) + r.z_axis.dot(a.z_axis);
+
+            let omega = torque / (alignment.abs() + f32::EPSILON);
+            let angle = omega.length();
+            if angle < CONVERGED {
+                break;
+            }
+            q = (glam::Quat::from_axis_angle(omega / angle, angle) * q).normalize();
+        }
+        q
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_rotation_eq(a: glam::Quat, b: glam::Quat) {
+        assert!(a.angle_between(b) < 1e-3, "{a} != {b}");
+    }
+
+    #[test]
+    fn shape_match_known_rotation() {
+        let expected = glam::Quat::from_euler(glam::EulerRot::YXZ, 1.2, -0.4, 2.5);
+        let rest = [
+            glam::Vec3::X,
+            glam::Vec3::NEG_Y,
+            glam::vec3(0.3, 0.5, 1.0).normalize(),
+            glam::vec3(-1.0, 0.2, -0.1).normalize(),
+        ];
+
+        let mut shape = ShapeMatch::new();
+        for p in rest {
+            shape.add(p, expected * p, 1.0);
+        }
+        assert_eq!(shape.weight(), 4.0);
+        assert_rotation_eq(shape.rotation(glam::Quat::IDENTITY, 64), expected);
+    }
+
+    #[test]
+    fn shape_match_weights() {
+        let expected = glam::Quat::from_rotation_z(0.8);
+
+        let mut shape = ShapeMatch::new();
+        shape.add(glam::Vec3::X, expected * glam::Vec3::X, 1.0);
+        shape.add(glam::Vec3::Y, expected * glam::Vec3::Y, 1.0);
+        // a soft link pulling the other way barely matters
+        shape.add(glam::Vec3::Z, glam::Vec3::NEG_X, 1e-4);
+        assert_rotation_eq(shape.rotation(glam::Quat::IDENTITY, 64), expected);
+    }
+
+    #[test]
+    fn shape_match_underdetermined() {
+        let twist = glam::Quat::from_rotation_x(0.5);
+
+        // a single pair leaves the twist around it to the guess
+        let mut shape = ShapeMatch::new();
+        shape.add(glam::Vec3::X, glam::Vec3::X, 1.0);
+        assert_rotation_eq(shape.rotation(twist, DEFAULT_MATCH_ITERATIONS), twist);
+
+        let swing = glam::Quat::from_rotation_z(0.3);
+        let mut shape = ShapeMatch::new();
+        shape.add(glam::Vec3::X, swing * glam::Vec3::X, 1.0);
+        let rotation = shape.rotation(twist, 64);
+        assert!((rotation * glam::Vec3::X).abs_diff_eq(swing * glam::Vec3::X, 1e-3));
+
+        // nothing to match
+        let shape = ShapeMatch::new();
+        assert_rotation_eq(shape.rotation(twist, DEFAULT_MATCH_ITERATIONS), twist);
+    }
+}
@@ -0,0 +1,438 @@
+/// Magic bytes at the start of every snapshot.
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"RZSN";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    /// The data does not start with [`SNAPSHOT_MAGIC`].
+    BadMagic,
+    /// The snapshot was written by an unsupported format version.
+    UnsupportedVersion(u32),
+    /// The data ended before the snapshot was fully read.
+    UnexpectedEof,
+    /// The data was read successfully but does not describe a valid state.
+    Corrupt(&'static str),
+}
+
+impl std::fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not a snapshot: bad magic bytes"),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {version}")
+            }
+            Self::UnexpectedEof => write!(f, "snapshot ended unexpectedly"),
+            Self::Corrupt(what) => write!(f, "corrupt snapshot: {what}"),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+/// A value that can be written to and read back from a snapshot.
+///
+/// Values are stored as little-endian binary, without any padding or
+/// field names: the layout is entirely defined by the order of calls.
+pub trait Snapshot: Sized {
+    fn save(&self, writer: &mut SnapshotWriter);
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError>;
+}
+
+#[derive(Clone, Debug)]
+pub struct SnapshotWriter {
+    bytes: Vec<u8>,
+}
+
+impl SnapshotWriter {
+    /// Start a new snapshot of the given format `version`.
+    pub fn new(version: u32) -> Self {
+        let mut writer = Self { bytes: Vec::new() };
+        writer.write_bytes(&SNAPSHOT_MAGIC);
+        writer.write(&version);
+        writer
+    }
+
+    #[inline]
+    pub fn write<T: Snapshot>(&mut self, value: &T) {
+        value.save(self);
+    }
+
+    /// Write a length-prefixed slice.
+    pub fn write_slice<T: Snapshot>(&mut self, values: &[T]) {
+        self.write(&(values.len() as u32));
+        values.iter().for_each(|value| value.save(self));
+    }
+
+    #[inline]
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SnapshotReader<'a> {
+    bytes: &'a [u8],
+    cursor: usize,
+    version: u32,
+}
+
+impl<'a> SnapshotReader<'a> {
+    /// Start reading the snapshot in `bytes`.
+    ///
+    /// # Errors
+    /// Will return an error if `bytes` does not start with a snapshot header.
+    pub fn new(bytes: &'a [u8]) -> Result<Self, SnapshotError> {
+        let mut reader = Self {
+            bytes,
+            cursor: 0,
+            version: 0,
+        };
+        if reader.read_bytes(SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        reader.version = reader.read()?;
+        Ok(reader)
+    }
+
+    /// The format version the snapshot was written with.
+    #[inline]
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    #[inline]
+    pub fn read<T: Snapshot>(&mut self) -> Result<T, SnapshotError> {
+        T::restore(self)
+    }
+
+    /// Read a slice written with [`SnapshotWriter::write_slice`].
+    pub fn read_vec<T: Snapshot>(&mut self) -> Result<Vec<T>, SnapshotError> {
+        let len = self.read::<u32>()? as usize;
+        // don't trust the length for the allocation
+        let mut values = Vec::with_capacity(len.min(self.bytes.len() - self.cursor));
+        for _ in 0..len {
+            values.push(self.read()?);
+        }
+        Ok(values)
+    }
+
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        let end = self.cursor.checked_add(len);
+        let bytes = end
+            .and_then(|end| self.bytes.get(self.cursor..end))
+            .ok_or(SnapshotError::UnexpectedEof)?;
+        self.cursor += len;
+        Ok(bytes)
+    }
+
+    /// Returns `true` once all bytes of the snapshot were read.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.cursor == self.bytes.len()
+    }
+}
+
+macro_rules! snapshot_num {
+    ($($ty:ty),*) => {
+        $(
+            impl Snapshot for $ty {
+                #[inline]
+                fn save(&self, writer: &mut SnapshotWriter) {
+                    writer.write_bytes(&self.to_le_bytes());
+                }
+
+                #[inline]
+                fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+                    let bytes = reader.read_bytes(size_of::<$ty>())?;
+                    Ok(<$ty>::from_le_bytes(bytes.try_into().expect("sized read")))
+                }
+            }
+        )*
+    };
+}
+
+snapshot_num!(u8, u32, u64, f32);
+
+impl Snapshot for bool {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&(*self as u8));
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        match reader.read::<u8>()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SnapshotError::Corrupt("invalid bool")),
+        }
+    }
+}
+
+impl<T: Snapshot> Snapshot for Option<T> {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.is_some());
+        if let Some(value) = self {
+            writer.write(value);
+        }
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        if reader.read::<bool>()? {
+            Ok(Some(reader.read()?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl Snapshot for String {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&(self.len() as u32));
+        writer.write_bytes(self.as_bytes());
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        let len = reader.read::<u32>()? as usize;
+        let bytes = reader.read_bytes(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| SnapshotError::Corrupt("invalid utf-8"))
+    }
+}
+
+impl<T: Snapshot> Snapshot for Vec<T> {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write_slice(self);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        reader.read_vec()
+    }
+}
+
+impl<T: Snapshot + Copy + Default, const N: usize> Snapshot for [T; N] {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        self.iter().for_each(|value| value.save(writer));
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        let mut values = [T::default(); N];
+        for value in &mut values {
+            *value = reader.read()?;
+        }
+        Ok(values)
+    }
+}
+
+impl<A: Snapshot, B: Snapshot> Snapshot for (A, B) {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.0);
+        writer.write(&self.1);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok((reader.read()?, reader.read()?))
+    }
+}
+
+impl Snapshot for glam::Vec3 {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.to_array());
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self::from_array(reader.read()?))
+    }
+}
+
+impl Snapshot for glam::Vec4 {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.to_array());
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self::from_array(reader.read()?))
+    }
+}
+
+impl Snapshot for glam::Quat {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.to_array());
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self::from_array(reader.read()?))
+    }
+}
+
+impl Snapshot for glam::Mat3 {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.to_cols_array());
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self::from_cols_array(&reader.read()?))
+    }
+}
+
+/// The handle bookkeeping of a table or column.
+///
+/// Tables are restored with their exact handle maps and free list, so
+/// handles taken before a snapshot stay valid after restoring it.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HandleMaps {
+    pub handles: Vec<u32>,
+    pub slots_map: Vec<u32>,
+    pub free_list: Vec<u32>,
+}
+
+impl HandleMaps {
+    pub fn new(handles: &[u32], slots_map: &[u32], free_list: &[u32]) -> Self {
+        Self {
+            handles: handles.to_vec(),
+            slots_map: slots_map.to_vec(),
+            free_list: free_list.to_vec(),
+        }
+    }
+
+    /// Amount of contiguous rows, including the degenerate row `0`.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    /// Check that a restored `column` has one value per contiguous row.
+    pub fn check_column<T>(&self, column: &[T]) -> Result<(), SnapshotError> {
+        if column.len() == self.len() {
+            Ok(())
+        } else {
+            Err(SnapshotError::Corrupt(
+                "column length does not match handles",
+            ))
+        }
+    }
+
+    /// Returns `true` if `handle` maps to a contiguous row that maps back to
+    /// it.
+    #[inline]
+    pub fn is_live(&self, handle: u32) -> bool {
+        match self.slots_map.get(handle as usize) {
+            Some(&0) | None => false,
+            Some(&index) => self.handles.get(index as usize) == Some(&handle),
+        }
+    }
+
+    /// Check that the handles, slots map and free list agree with one
+    /// another.
+    ///
+    /// Every contiguous row must be mapped to by its handle, and no free
+    /// handle may be live.
+    pub fn validate(&self) -> Result<(), SnapshotError> {
+        if self.is_empty() || self.slots_map.is_empty() {
+            return Err(SnapshotError::Corrupt("missing degenerate row"));
+        }
+
+        for (index, &handle) in self.handles.iter().enumerate().skip(1) {
+            if self.slots_map.get(handle as usize) != Some(&(index as u32)) {
+                return Err(SnapshotError::Corrupt("handle does not map to its row"));
+            }
+        }
+        for &handle in &self.free_list {
+            if handle as usize >= self.slots_map.len() || self.is_live(handle) {
+                return Err(SnapshotError::Corrupt("free list holds a live handle"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Split into the handles, slots map and free list, to overwrite those
+    /// of a freshly refilled table.
+    ///
+    /// The table must hold exactly [`HandleMaps::len`] contiguous rows.
+    #[inline]
+    pub fn into_parts(self) -> (Vec<u32>, Vec<u32>, Vec<u32>) {
+        (self.handles, self.slots_map, self.free_list)
+    }
+}
+
+impl Snapshot for HandleMaps {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.handles);
+        writer.write(&self.slots_map);
+        writer.write(&self.free_list);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        let maps = Self {
+            handles: reader.read()?,
+            slots_map: reader.read()?,
+            free_list: reader.read()?,
+        };
+        maps.validate()?;
+        Ok(maps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_round_trip() {
+        let mut writer = SnapshotWriter::new(7);
+        writer.write(&42u32);
+        writer.write(&Some(1.5f32));
+        writer.write(&vec![glam::Vec3::X, glam::Vec3::NEG_Z]);
+        writer.write(&glam::Quat::from_rotation_y(1.0));
+        let bytes = writer.finish();
+
+        let mut reader = SnapshotReader::new(&bytes).unwrap();
+        assert_eq!(reader.version(), 7);
+        assert_eq!(reader.read::<u32>(), Ok(42));
+        assert_eq!(reader.read::<Option<f32>>(), Ok(Some(1.5)));
+        assert_eq!(
+            reader.read::<Vec<glam::Vec3>>(),
+            Ok(vec![glam::Vec3::X, glam::Vec3::NEG_Z])
+        );
+        assert_eq!(
+            reader.read::<glam::Quat>(),
+            Ok(glam::Quat::from_rotation_y(1.0))
+        );
+        assert!(reader.is_empty());
+        assert_eq!(reader.read::<u32>(), Err(SnapshotError::UnexpectedEof));
+    }
+
+    #[test]
+    fn snapshot_bad_magic() {
+        assert_eq!(
+            SnapshotReader::new(b"NOPE\0\0\0\0").unwrap_err(),
+            SnapshotError::BadMagic
+        );
+    }
+
+    #[test]
+    fn snapshot_handle_maps_validate() {
+        let read_back = |maps: HandleMaps| {
+            let mut writer = SnapshotWriter::new(1);
+            writer.write(&maps);
+            let bytes = writer.finish();
+            SnapshotReader::new(&bytes).unwrap().read::<HandleMaps>()
+        };
+
+        // handles 1 and 3 are live in rows 2 and 1, handle 2 is free
+        let maps = HandleMaps::new(&[0, 3, 1], &[0, 2, 0, 1], &[2]);
+        assert_eq!(read_back(maps.clone()), Ok(maps));
+
+        let wrong_row = HandleMaps::new(&[0, 3, 1], &[0, 1, 0, 2], &[2]);
+        let out_of_range = HandleMaps::new(&[0, 4, 1], &[0, 2, 0, 1], &[2]);
+        let free_live = HandleMaps::new(&[0, 3, 1], &[0, 2, 0, 1], &[3]);
+        for maps in [wrong_row, out_of_range, free_live] {
+            assert!(matches!(read_back(maps), Err(SnapshotError::Corrupt(_))));
+        }
+    }
+}
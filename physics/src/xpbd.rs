@@ -1,11 +1,20 @@
+use std::collections::{BTreeMap, HashMap};
+
 use ethel::state::data::Column;
 use janus::context::DeltaTime;
 
+use crate::{
+    colouring::ConstraintColouring,
+    material::{Material, Surface},
+    snapshot::{HandleMaps, Snapshot, SnapshotError, SnapshotReader, SnapshotWriter},
+};
+
 #[derive(Debug, Clone, Copy, Default)]
 pub struct XpbdNodeOptions {
     pos: glam::Vec3,
     mass: f32,
     fixed: bool,
+    surface: Surface,
 }
 
 impl XpbdNodeOptions {
@@ -14,6 +23,19 @@ impl XpbdNodeOptions {
             pos,
             mass,
             fixed: false,
+            surface: Surface::DEFAULT,
+        }
+    }
+
+    /// Create a node holding `volume` units of `material`.
+    ///
+    /// The mass of the node is derived from the density of the `material`.
+    pub const fn from_material(pos: glam::Vec3, volume: f32, material: &Material) -> Self {
+        Self {
+            pos,
+            mass: material.mass_of(volume),
+            fixed: false,
+            surface: material.surface(),
         }
     }
 
@@ -22,8 +44,51 @@ impl XpbdNodeOptions {
             pos: self.pos,
             mass: self.mass,
             fixed,
+            surface: self.surface,
+        }
+    }
+
+    /// Use the contact response of `material` for this node.
+    ///
+    /// This does not change the mass of the node; see
+    /// [`XpbdNodeOptions::from_material`].
+    pub const fn with_material(self, material: &Material) -> Self {
+        Self {
+            pos: self.pos,
+            mass: self.mass,
+            fixed: self.fixed,
+            surface: material.surface(),
         }
     }
+
+    pub const fn with_surface(self, surface: Surface) -> Self {
+        Self {
+            pos: self.pos,
+            mass: self.mass,
+            fixed: self.fixed,
+            surface,
+        }
+    }
+
+    #[inline]
+    pub const fn pos(&self) -> glam::Vec3 {
+        self.pos
+    }
+
+    #[inline]
+    pub const fn mass(&self) -> f32 {
+        self.mass
+    }
+
+    #[inline]
+    pub const fn is_fixed(&self) -> bool {
+        self.fixed
+    }
+
+    #[inline]
+    pub const fn surface(&self) -> Surface {
+        self.surface
+    }
 }
 
 impl XpbdLinkOptions {
@@ -31,6 +96,18 @@ impl XpbdLinkOptions {
         Self {
             compliance,
             rest_length: None,
+            limits: LinkLimits::DEFAULT,
+            plasticity: Plasticity::ELASTIC,
+        }
+    }
+
+    /// Create a link with the compliance and breaking limits of `material`.
+    pub const fn from_material(material: &Material) -> Self {
+        Self {
+            compliance: material.compliance,
+            rest_length: None,
+            limits: material.limits(),
+            plasticity: material.plasticity,
         }
     }
 
@@ -38,6 +115,8 @@ impl XpbdLinkOptions {
         Self {
             compliance,
             rest_length: Some(rest_length),
+            limits: LinkLimits::DEFAULT,
+            plasticity: Plasticity::ELASTIC,
         }
     }
 
@@ -45,14 +124,169 @@ impl XpbdLinkOptions {
         Self {
             compliance: self.compliance,
             rest_length: Some(rest_length),
+            limits: self.limits,
+            plasticity: self.plasticity,
+        }
+    }
+
+    /// Use the compliance, breaking limits and plasticity of `material` for
+    /// this link.
+    ///
+    /// The rest length of the link is left untouched.
+    pub const fn and_material(self, material: &Material) -> Self {
+        Self {
+            compliance: material.compliance,
+            rest_length: self.rest_length,
+            limits: material.limits(),
+            plasticity: material.plasticity,
+        }
+    }
+
+    /// Set the breaking limits of the link.
+    ///
+    /// Both `tension` and `compression` are force magnitudes; see
+    /// [`LinkLimits`].
+    pub const fn and_limits(self, tension: f32, compression: f32) -> Self {
+        Self {
+            compliance: self.compliance,
+            rest_length: self.rest_length,
+            limits: LinkLimits::new(tension, compression),
+            plasticity: self.plasticity,
+        }
+    }
+
+    /// Let the link permanently deform before breaking.
+    ///
+    /// A plastic link ignores its breaking limits; it breaks once it reaches
+    /// the ultimate strain of its `plasticity` instead.
+    pub const fn and_plasticity(self, plasticity: Plasticity) -> Self {
+        Self {
+            compliance: self.compliance,
+            rest_length: self.rest_length,
+            limits: self.limits,
+            plasticity,
         }
     }
+
+    #[inline]
+    pub const fn compliance(&self) -> f32 {
+        self.compliance
+    }
+
+    /// The explicit rest length of the link.
+    ///
+    /// If `None`, the rest length is the distance between its nodes when the
+    /// lattice is exported.
+    #[inline]
+    pub const fn rest_length(&self) -> Option<f32> {
+        self.rest_length
+    }
+
+    #[inline]
+    pub const fn limits(&self) -> LinkLimits {
+        self.limits
+    }
+
+    #[inline]
+    pub const fn plasticity(&self) -> Plasticity {
+        self.plasticity
+    }
 }
 
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy)]
 pub struct XpbdLinkOptions {
     compliance: f32,
     rest_length: Option<f32>,
+    limits: LinkLimits,
+    plasticity: Plasticity,
+}
+
+impl Default for XpbdLinkOptions {
+    fn default() -> Self {
+        Self::new(0.0)
+    }
+}
+
+pub const DEFAULT_TENSION_LIMIT: f32 = 20_000.0;
+pub const DEFAULT_COMPRESSION_LIMIT: f32 = 52_000.0;
+
+/// The forces at which a link breaks.
+///
+/// Both limits are magnitudes: a link breaks once the force it exerts while
+/// stretched exceeds `tension`, or once the force it exerts while compressed
+/// exceeds `compression`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkLimits {
+    pub tension: f32,
+    pub compression: f32,
+}
+
+impl LinkLimits {
+    pub const DEFAULT: Self = Self::new(DEFAULT_TENSION_LIMIT, DEFAULT_COMPRESSION_LIMIT);
+
+    pub const fn new(tension: f32, compression: f32) -> Self {
+        Self {
+            tension,
+            compression,
+        }
+    }
+
+    /// Returns `true` if a link constraint `force` exceeds these limits.
+    ///
+    /// The sign of `force` follows the lagrange multiplier of the link:
+    /// negative while the link is stretched, positive while compressed.
+    #[inline]
+    pub fn exceeded_by(&self, force: f32) -> bool {
+        force <= -self.tension || force >= self.compression
+    }
+}
+
+impl Default for LinkLimits {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+/// Permanent deformation of a link.
+///
+/// While the force exerted by a link exceeds `yield_force`, its rest length
+/// creeps toward its current length by `creep` of the difference every step.
+/// Every unit of strain absorbed this way raises `yield_force` by
+/// `hardening`.
+///
+/// The link breaks once its length deviates from its initial rest length by
+/// `ultimate_strain`, relative to the initial rest length.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plasticity {
+    pub yield_force: f32,
+    pub creep: f32,
+    pub hardening: f32,
+    pub ultimate_strain: f32,
+}
+
+impl Plasticity {
+    /// A link that never yields and breaks on its [`LinkLimits`].
+    pub const ELASTIC: Self = Self::new(f32::INFINITY, 0.0, 0.0, f32::INFINITY);
+
+    pub const fn new(yield_force: f32, creep: f32, hardening: f32, ultimate_strain: f32) -> Self {
+        Self {
+            yield_force,
+            creep,
+            hardening,
+            ultimate_strain,
+        }
+    }
+
+    #[inline]
+    pub const fn is_elastic(&self) -> bool {
+        self.yield_force == f32::INFINITY
+    }
+}
+
+impl Default for Plasticity {
+    fn default() -> Self {
+        Self::ELASTIC
+    }
 }
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -67,6 +301,9 @@ pub struct XpbdLatticeBuilder {
     nodes: Vec<XpbdNodeOptions>,
     links: Vec<XpbdLink>,
     stack: Vec<u32>,
+    groups: BTreeMap<String, LatticeGroup>,
+    /// Node IDs standing in for live nodes, and their table handles
+    external: BTreeMap<u32, u32>,
 }
 
 impl XpbdLatticeBuilder {
@@ -79,6 +316,8 @@ impl XpbdLatticeBuilder {
             nodes: Vec::with_capacity(capacity),
             links: Vec::with_capacity(capacity * 3),
             stack: Vec::with_capacity(capacity / 3),
+            groups: BTreeMap::new(),
+            external: BTreeMap::new(),
         }
     }
 
@@ -102,6 +341,62 @@ impl XpbdLatticeBuilder {
         id as u32
     }
 
+    /// Push a node already live in `nodes` in the hierarchy, by its `handle`.
+    ///
+    /// This works like [`node`], but the node is not created on export:
+    /// links to it bind to the live node instead. This can be used to extend
+    /// a lattice that is already simulated, such as bolting scaffolding onto
+    /// a standing structure.
+    ///
+    /// The options of the node are read from `nodes`; they are only used to
+    /// validate and weld the lattice.
+    ///
+    /// # Panics
+    /// Will panic if `handle` is not a live node of `nodes`.
+    /// See [`XpbdLatticeBuilder::try_external`] for a fallible alternative.
+    ///
+    /// # Returns
+    /// Returns the index of the node in the hierarchy.
+    ///
+    /// [`node`]: XpbdLatticeBuilder::node
+    pub fn external(&mut self, handle: u32, nodes: &NodesRowTable) -> u32 {
+        self.try_external(handle, nodes)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fallible version of [`XpbdLatticeBuilder::external`].
+    ///
+    /// # Errors
+    /// Will return [`LatticeError::UnknownHandle`] if `handle` is not a live
+    /// node of `nodes`.
+    pub fn try_external(
+        &mut self,
+        handle: u32,
+        nodes: &NodesRowTable,
+    ) -> Result<u32, LatticeError> {
+        let index = nodes
+            .get_indirect(handle)
+            .filter(|_| handle != 0)
+            .ok_or(LatticeError::UnknownHandle(handle))? as usize;
+
+        let options = XpbdNodeOptions {
+            pos: nodes.current_pos_slice()[index],
+            mass: nodes.mass_slice()[index],
+            fixed: nodes.inv_mass_slice()[index] == 0.0,
+            surface: nodes.surface_slice()[index],
+        };
+        let id = self.node(options);
+        self.external.insert(id, handle);
+        Ok(id)
+    }
+
+    /// Returns the handle of the live node `node_id` stands in for, if it was
+    /// pushed with [`XpbdLatticeBuilder::external`].
+    #[inline]
+    pub fn external_handle(&self, node_id: u32) -> Option<u32> {
+        self.external.get(&node_id).copied()
+    }
+
     /// Create a contraint between the last 2 nodes in the stack.
     ///
     /// This effectively creates a link between the current node and its
@@ -119,25 +414,32 @@ impl XpbdLatticeBuilder {
     ///
     /// # Panics
     /// Will panic if there are less than 2 nodes currently in the stack.
+    /// See [`XpbdLatticeBuilder::try_link`] for a fallible alternative.
     ///
     /// # Returns
     /// Returns the index of the newly created link.
     pub fn link(&mut self, options: XpbdLinkOptions) -> u32 {
-        debug_assert!(
-            self.stack.len() >= 2,
-            "attempted to create lattice link with less than 2 nodes in stack"
-        );
+        self.try_link(options).unwrap_or_else(|e| panic!("{e}"))
+    }
 
-        let id = self.stack.pop().expect("stack must have >=2 nodes");
-        let parent = self.stack.last().expect("stack must have >=2 nodes");
+    /// Fallible version of [`XpbdLatticeBuilder::link`].
+    ///
+    /// # Errors
+    /// Will return [`LatticeError::StackUnderflow`] if there are less than 2
+    /// nodes currently in the stack. The stack is left untouched on error.
+    pub fn try_link(&mut self, options: XpbdLinkOptions) -> Result<u32, LatticeError> {
+        let &[.., parent, id] = self.stack.as_slice() else {
+            return Err(LatticeError::StackUnderflow);
+        };
+        self.stack.pop();
 
         let link_id = self.links.len();
         self.links.push(XpbdLink {
-            node_a: *parent,
+            node_a: parent,
             node_b: id,
             options,
         });
-        link_id as u32
+        Ok(link_id as u32)
     }
 
     /// Create a contraint between the current node in the stack and an
@@ -151,29 +453,33 @@ impl XpbdLatticeBuilder {
     /// [`link`](XpbdLatticeBuilder::link) function.
     ///
     /// # Panics
-    /// Will panic if there are less than 2 nodes currently in the stack or if
-    /// `node_id` does not point to a valid node in the stack.
+    /// Will panic if there are no nodes currently in the stack or if
+    /// `node_id` does not point to a valid node.
     /// Will also panic if `node_id` corresponds to the current node in the
     /// stack, as a node cannot be linked to itself.
+    /// See [`XpbdLatticeBuilder::try_link_to`] for a fallible alternative.
     ///
     /// # Returns
     /// Returns the index of the newly created link.
     pub fn link_to(&mut self, node_id: u32, options: XpbdLinkOptions) -> u32 {
-        debug_assert!(
-            self.stack.len() >= 1,
-            "attempted to create lattice link with no nodes in the stack"
-        );
-
-        let id = *self.stack.last().expect("stack must be populated");
-        debug_assert!(id != node_id, "cannot links node {id} to itself");
+        self.try_link_to(node_id, options)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
 
-        let link_id = self.links.len();
-        self.links.push(XpbdLink {
-            node_a: id,
-            node_b: node_id,
-            options,
-        });
-        link_id as u32
+    /// Fallible version of [`XpbdLatticeBuilder::link_to`].
+    ///
+    /// # Errors
+    /// Will return:
+    /// * [`LatticeError::StackUnderflow`] if there are no nodes in the stack.
+    /// * [`LatticeError::UnknownNode`] if `node_id` is not a valid node.
+    /// * [`LatticeError::SelfLink`] if `node_id` is the current node.
+    pub fn try_link_to(
+        &mut self,
+        node_id: u32,
+        options: XpbdLinkOptions,
+    ) -> Result<u32, LatticeError> {
+        let &id = self.stack.last().ok_or(LatticeError::StackUnderflow)?;
+        self.try_link_nodes(id, node_id, options)
     }
 
     /// Create a link between two nodes `node_a` and `node_b`.
@@ -190,22 +496,35 @@ impl XpbdLatticeBuilder {
     /// [`node`]: XpbdLatticeBuilder::node
     ///
     /// # Panics
-    /// Will panic if either `node_a` of `node_b` do not point to a valid node ID.
+    /// Will panic if either `node_a` of `node_b` do not point to a valid node
+    /// ID, or if both are the same node.
+    /// See [`XpbdLatticeBuilder::try_link_nodes`] for a fallible alternative.
     ///
     /// # Returns
     /// Returns the index of the newly created link.
     pub fn link_nodes(&mut self, node_a: u32, node_b: u32, options: XpbdLinkOptions) -> u32 {
-        #[cfg(debug_assertions)]
-        {
-            let node_count = self.nodes.len() as u32;
-            debug_assert!(
-                node_a < node_count,
-                "attempted to create a link containing invalid node {node_a}"
-            );
-            debug_assert!(
-                node_b < node_count,
-                "attempted to create a link containing invalid node {node_b}"
-            );
+        self.try_link_nodes(node_a, node_b, options)
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// Fallible version of [`XpbdLatticeBuilder::link_nodes`].
+    ///
+    /// # Errors
+    /// Will return:
+    /// * [`LatticeError::UnknownNode`] if either node is not a valid node.
+    /// * [`LatticeError::SelfLink`] if `node_a` and `node_b` are the same.
+    pub fn try_link_nodes(
+        &mut self,
+        node_a: u32,
+        node_b: u32,
+        options: XpbdLinkOptions,
+    ) -> Result<u32, LatticeError> {
+        let node_count = self.nodes.len() as u32;
+        if let Some(node) = [node_a, node_b].into_iter().find(|&n| n >= node_count) {
+            return Err(LatticeError::UnknownNode(node));
+        }
+        if node_a == node_b {
+            return Err(LatticeError::SelfLink(node_a));
         }
 
         let link_id = self.links.len();
@@ -214,20 +533,395 @@ impl XpbdLatticeBuilder {
             node_b,
             options,
         });
-        link_id as u32
+        Ok(link_id as u32)
+    }
+
+    /// Check the lattice for defects that would make it misbehave once
+    /// exported.
+    ///
+    /// Unlike the `try_` functions, this looks at the lattice as a whole:
+    /// it reports every defect it finds, rather than the first one.
+    ///
+    /// # Errors
+    /// Will return all defects found, in node then link order; see
+    /// [`LatticeError`].
+    pub fn validate(&self) -> Result<(), Vec<LatticeError>> {
+        let mut errors = Vec::new();
+
+        let mut degree = vec![0u32; self.nodes.len()];
+        let mut seen = HashMap::with_capacity(self.links.len());
+        let mut link_errors = Vec::new();
+
+        for (id, link) in self.links.iter().enumerate() {
+            let id = id as u32;
+            let (a, b) = (link.node_a, link.node_b);
+
+            let (Some(node_a), Some(node_b)) =
+                (self.nodes.get(a as usize), self.nodes.get(b as usize))
+            else {
+                let node = if (a as usize) < self.nodes.len() {
+                    b
+                } else {
+                    a
+                };
+                link_errors.push(LatticeError::UnknownNode(node));
+                continue;
+            };
+            if a == b {
+                link_errors.push(LatticeError::SelfLink(a));
+                continue;
+            }
+            degree[a as usize] += 1;
+            degree[b as usize] += 1;
+
+            if let Some(&first) = seen.get(&(a.min(b), a.max(b))) {
+                link_errors.push(LatticeError::DuplicateLink {
+                    link: id,
+                    duplicate_of: first,
+                });
+            } else {
+                seen.insert((a.min(b), a.max(b)), id);
+            }
+
+            let length = link
+                .options
+                .rest_length
+                .unwrap_or_else(|| node_a.pos.distance(node_b.pos));
+            if length <= f32::EPSILON {
+                link_errors.push(LatticeError::ZeroLengthLink(id));
+            }
+        }
+
+        for (id, node) in self.nodes.iter().enumerate() {
+            let id = id as u32;
+            // fixed nodes ignore their mass
+            if !node.fixed && (node.mass.is_nan() || node.mass <= 0.0) {
+                errors.push(LatticeError::ZeroMassNode(id));
+            }
+            if degree[id as usize] == 0 {
+                errors.push(LatticeError::UnlinkedNode(id));
+            }
+        }
+        errors.append(&mut link_errors);
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Append a copy of the lattice of `other`, placed by `transform`.
+    ///
+    /// Nodes and links of `other` are given new IDs after the ones already in
+    /// this builder; the stack of this builder is left untouched.
+    ///
+    /// The scale of the `transform` also applies to explicit link rest
+    /// lengths. Masses are left untouched. External nodes of `other` stay
+    /// external, but their position is transformed like any other node.
+    ///
+    /// # Returns
+    /// A mapping of the IDs of `other` to their IDs in this builder, which
+    /// can be used to link the appended nodes with [`link_nodes`].
+    ///
+    /// [`link_nodes`]: XpbdLatticeBuilder::link_nodes
+    pub fn append(
+        &mut self,
+        other: &XpbdLatticeBuilder,
+        transform: LatticeTransform,
+    ) -> LatticeRemap {
+        let node_offset = self.nodes.len() as u32;
+        let link_offset = self.links.len() as u32;
+
+        self.nodes
+            .extend(other.nodes.iter().map(|node| XpbdNodeOptions {
+                pos: transform.apply(node.pos),
+                mass: node.mass,
+                fixed: node.fixed,
+                surface: node.surface,
+            }));
+        self.links.extend(other.links.iter().map(|link| XpbdLink {
+            node_a: link.node_a + node_offset,
+            node_b: link.node_b + node_offset,
+            options: XpbdLinkOptions {
+                compliance: link.options.compliance,
+                rest_length: link.options.rest_length.map(|l| l * transform.scale),
+                limits: link.options.limits,
+                plasticity: link.options.plasticity,
+            },
+        }));
+
+        self.external.extend(
+            other
+                .external
+                .iter()
+                .map(|(&id, &handle)| (id + node_offset, handle)),
+        );
+
+        for (name, group) in &other.groups {
+            let target = self.groups.entry(name.clone()).or_default();
+            target
+                .nodes
+                .extend(group.nodes.iter().map(|&n| n + node_offset));
+            target
+                .links
+                .extend(group.links.iter().map(|&l| l + link_offset));
+        }
+
+        LatticeRemap {
+            nodes: (node_offset..self.nodes.len() as u32).collect(),
+            links: (link_offset..self.links.len() as u32).collect(),
+        }
+    }
+
+    /// Merge nodes closer than `tolerance` to one another, along with the
+    /// links that end up connecting the same nodes.
+    ///
+    /// Each node is merged into the first node within `tolerance` of it, if
+    /// any, which keeps its position and surface. Merged nodes add up their
+    /// masses, and are fixed if any of them was fixed.
+    ///
+    /// Links between merged nodes act in parallel: their compliances combine
+    /// like parallel springs and their breaking limits add up. Links that
+    /// would connect a node to itself are removed.
+    ///
+    /// External nodes are never merged into another node, but other nodes
+    /// may be merged into them; the live node is left untouched.
+    ///
+    /// IDs are compacted: the remaining nodes and links keep their relative
+    /// order. Groups and the stack are remapped accordingly.
+    ///
+    /// # Panics
+    /// Will panic if `tolerance` is not positive.
+    ///
+    /// # Returns
+    /// A mapping of the IDs before the weld to the IDs after it. Removed
+    /// links map to [`LatticeRemap::REMOVED`].
+    pub fn weld(&mut self, tolerance: f32) -> LatticeRemap {
+        assert!(tolerance > 0.0, "weld tolerance must be positive");
+
+        let cell_of = |pos: glam::Vec3| (pos / tolerance).floor().as_ivec3();
+        let neighbours = |cell: glam::IVec3| {
+            (-1..=1).flat_map(move |x| {
+                (-1..=1).flat_map(move |y| (-1..=1).map(move |z| cell + glam::ivec3(x, y, z)))
+            })
+        };
+
+        let mut cells = HashMap::<glam::IVec3, Vec<u32>>::new();
+        let mut nodes = Vec::<XpbdNodeOptions>::with_capacity(self.nodes.len());
+        let mut node_map = Vec::with_capacity(self.nodes.len());
+        let mut external = BTreeMap::new();
+        for (old_id, node) in self.nodes.iter().enumerate() {
+            let cell = cell_of(node.pos);
+            let handle = self.external.get(&(old_id as u32)).copied();
+            // external nodes keep their identity
+            let target = handle.is_none().then(|| {
+                neighbours(cell)
+                    .filter_map(|cell| cells.get(&cell))
+                    .flatten()
+                    .copied()
+                    .find(|&id| nodes[id as usize].pos.distance(node.pos) <= tolerance)
+            });
+
+            let id = match target.flatten() {
+                Some(id) if external.contains_key(&id) => id,
+                Some(id) => {
+                    let target = &mut nodes[id as usize];
+                    target.mass += node.mass;
+                    target.fixed |= node.fixed;
+                    id
+                }
+                None => {
+                    let id = nodes.len() as u32;
+                    nodes.push(*node);
+                    cells.entry(cell).or_default().push(id);
+                    if let Some(handle) = handle {
+                        external.insert(id, handle);
+                    }
+                    id
+                }
+            };
+            node_map.push(id);
+        }
+
+        let mut pairs = HashMap::<(u32, u32), u32>::with_capacity(self.links.len());
+        let mut links = Vec::<XpbdLink>::with_capacity(self.links.len());
+        let mut link_map = Vec::with_capacity(self.links.len());
+        for link in &self.links {
+            let a = node_map[link.node_a as usize];
+            let b = node_map[link.node_b as usize];
+            if a == b {
+                link_map.push(LatticeRemap::REMOVED);
+                continue;
+            }
+
+            let id = *pairs.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                links.push(XpbdLink {
+                    node_a: a,
+                    node_b: b,
+                    options: XpbdLinkOptions {
+                        // merged below
+                        compliance: f32::INFINITY,
+                        rest_length: link.options.rest_length,
+                        limits: LinkLimits::new(0.0, 0.0),
+                        plasticity: link.options.plasticity,
+                    },
+                });
+                links.len() as u32 - 1
+            });
+
+            let merged = &mut links[id as usize].options;
+            merged.compliance = parallel_compliance(merged.compliance, link.options.compliance);
+            merged.limits.tension += link.options.limits.tension;
+            merged.limits.compression += link.options.limits.compression;
+            link_map.push(id);
+        }
+
+        for group in self.groups.values_mut() {
+            let mut nodes = Vec::with_capacity(group.nodes.len());
+            for &node in &group.nodes {
+                let node = node_map[node as usize];
+                if !nodes.contains(&node) {
+                    nodes.push(node);
+                }
+            }
+            let mut links = Vec::with_capacity(group.links.len());
+            for &link in &group.links {
+                let link = link_map[link as usize];
+                if link != LatticeRemap::REMOVED && !links.contains(&link) {
+                    links.push(link);
+                }
+            }
+            *group = LatticeGroup { nodes, links };
+        }
+
+        self.stack
+            .iter_mut()
+            .for_each(|node| *node = node_map[*node as usize]);
+        self.stack.dedup();
+
+        self.nodes = nodes;
+        self.links = links;
+        self.external = external;
+        LatticeRemap {
+            nodes: node_map,
+            links: link_map,
+        }
+    }
+
+    /// Tag `nodes` with the group `name`, creating the group if needed.
+    ///
+    /// Group names are free-form, such as `anchors` or `floor:3`. Groups are
+    /// resolved to table handles on [`export`](XpbdLatticeBuilder::export).
+    ///
+    /// # Panics
+    /// Will panic if any of `nodes` is not a valid node ID.
+    /// See [`XpbdLatticeBuilder::try_tag_nodes`] for a fallible alternative.
+    pub fn tag_nodes(&mut self, name: &str, nodes: &[u32]) {
+        self.try_tag_nodes(name, nodes)
+            .unwrap_or_else(|e| panic!("{e}"));
+    }
+
+    /// Fallible version of [`XpbdLatticeBuilder::tag_nodes`].
+    ///
+    /// # Errors
+    /// Will return [`LatticeError::UnknownNode`] if any of `nodes` is not a
+    /// valid node ID. No node is tagged on error.
+    pub fn try_tag_nodes(&mut self, name: &str, nodes: &[u32]) -> Result<(), LatticeError> {
+        let node_count = self.nodes.len() as u32;
+        if let Some(&node) = nodes.iter().find(|&&n| n >= node_count) {
+            return Err(LatticeError::UnknownNode(node));
+        }
+
+        let group = self.group_mut(name);
+        for &node in nodes {
+            if !group.nodes.contains(&node) {
+                group.nodes.push(node);
+            }
+        }
+        Ok(())
+    }
+
+    /// Tag `links` with the group `name`, creating the group if needed.
+    ///
+    /// See [`XpbdLatticeBuilder::tag_nodes`].
+    ///
+    /// # Panics
+    /// Will panic if any of `links` is not a valid link ID.
+    /// See [`XpbdLatticeBuilder::try_tag_links`] for a fallible alternative.
+    pub fn tag_links(&mut self, name: &str, links: &[u32]) {
+        self.try_tag_links(name, links)
+            .unwrap_or_else(|e| panic!("{e}"));
+    }
+
+    /// Fallible version of [`XpbdLatticeBuilder::tag_links`].
+    ///
+    /// # Errors
+    /// Will return [`LatticeError::UnknownLink`] if any of `links` is not a
+    /// valid link ID. No link is tagged on error.
+    pub fn try_tag_links(&mut self, name: &str, links: &[u32]) -> Result<(), LatticeError> {
+        let link_count = self.links.len() as u32;
+        if let Some(&link) = links.iter().find(|&&l| l >= link_count) {
+            return Err(LatticeError::UnknownLink(link));
+        }
+
+        let group = self.group_mut(name);
+        for &link in links {
+            if !group.links.contains(&link) {
+                group.links.push(link);
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the node and link IDs tagged with `name`.
+    pub fn group(&self, name: &str) -> Option<&LatticeGroup> {
+        self.groups.get(name)
+    }
+
+    /// All groups of the lattice by name.
+    pub fn groups(&self) -> &BTreeMap<String, LatticeGroup> {
+        &self.groups
+    }
+
+    fn group_mut(&mut self, name: &str) -> &mut LatticeGroup {
+        self.groups.entry(name.to_owned()).or_default()
+    }
+
+    /// The options of every node, indexed by node ID.
+    #[inline]
+    pub fn nodes(&self) -> &[XpbdNodeOptions] {
+        &self.nodes
+    }
+
+    /// Iterate over the nodes and options of every link, in link ID order.
+    pub fn links(&self) -> impl ExactSizeIterator<Item = (u32, u32, &XpbdLinkOptions)> {
+        self.links
+            .iter()
+            .map(|link| (link.node_a, link.node_b, &link.options))
     }
 
     /// Export the current defined lattice structure into the given tables.
     ///
+    /// External nodes are not created: their links bind to the live nodes,
+    /// which must still be live in `nodes`.
+    ///
     /// # Returns
     /// A mapping of the [`LatticeIds`] between the indices of the nodes and
     /// links and the indirect indices of the actual nodes and links in their
     /// respective tables.
     pub fn export(mut self, nodes: &mut NodesRowTable, links: &mut LinksRowTable) -> LatticeIds {
+        let external = std::mem::take(&mut self.external);
         let node_ids = self
             .nodes
             .drain(..)
-            .map(|node_opt| {
+            .enumerate()
+            .map(|(id, node_opt)| {
+                if let Some(&handle) = external.get(&(id as u32)) {
+                    debug_assert!(nodes.get_indirect(handle).is_some());
+                    return handle;
+                }
+
                 let p_pos = node_opt.pos;
                 let c_pos = node_opt.pos;
                 let mass = node_opt.mass;
@@ -237,8 +931,12 @@ impl XpbdLatticeBuilder {
                 }
                 let forces = glam::Vec3::ZERO;
                 let velocity = glam::Vec3::ZERO;
+                let surface = node_opt.surface;
+                let sleeping = false;
 
-                nodes.put((p_pos, c_pos, mass, inv_mass, forces, velocity))
+                nodes.put((
+                    p_pos, c_pos, mass, inv_mass, forces, velocity, surface, sleeping,
+                ))
             })
             .collect::<Vec<_>>();
 
@@ -263,35 +961,219 @@ impl XpbdLatticeBuilder {
                     (p_a - p_b).length()
                 });
 
-                links.put((relation, compliance, rest_length, lambda))
+                let limits = link.options.limits;
+                let plasticity = link.options.plasticity;
+                let initial_length = rest_length;
+
+                links.put((
+                    relation,
+                    compliance,
+                    rest_length,
+                    lambda,
+                    limits,
+                    plasticity,
+                    initial_length,
+                ))
             })
             .collect::<Vec<_>>();
 
+        let groups = std::mem::take(&mut self.groups)
+            .into_iter()
+            .map(|(name, group)| {
+                let group = LatticeGroup {
+                    nodes: group.nodes.iter().map(|&n| node_ids[n as usize]).collect(),
+                    links: group.links.iter().map(|&l| link_ids[l as usize]).collect(),
+                };
+                (name, group)
+            })
+            .collect();
+
         LatticeIds {
             nodes: node_ids,
             links: link_ids,
+            groups,
+            external: external.into_values().collect(),
         }
     }
 }
 
-#[derive(Clone, Debug, Default)]
-pub struct LatticeIds {
-    pub nodes: Vec<u32>,
-    pub links: Vec<u32>,
+/// Placement of a lattice appended with [`XpbdLatticeBuilder::append`].
+///
+/// Positions are scaled, then rotated, then translated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LatticeTransform {
+    pub translation: glam::Vec3,
+    pub rotation: glam::Quat,
+    pub scale: f32,
 }
 
-pub const DEFAULT_SOLVE_ITERATIONS: u32 = 8;
-pub const DEFAULT_SUB_STEPS: u32 = 4;
-pub const DAMPING: f32 = 0.996;
+impl LatticeTransform {
+    pub const IDENTITY: Self = Self::new(glam::Vec3::ZERO, glam::Quat::IDENTITY, 1.0);
 
-ethel::table_spec! {
-    struct Nodes {
-        predicted_pos: glam::Vec3;
-        current_pos: glam::Vec3;
-        mass: f32;
-        inv_mass: f32;
-        forces: glam::Vec3;
-        velocity: glam::Vec3;
+    pub const fn new(translation: glam::Vec3, rotation: glam::Quat, scale: f32) -> Self {
+        Self {
+            translation,
+            rotation,
+            scale,
+        }
+    }
+
+    pub const fn from_translation(translation: glam::Vec3) -> Self {
+        Self::new(translation, glam::Quat::IDENTITY, 1.0)
+    }
+
+    pub const fn with_rotation(self, rotation: glam::Quat) -> Self {
+        Self {
+            translation: self.translation,
+            rotation,
+            scale: self.scale,
+        }
+    }
+
+    pub const fn with_scale(self, scale: f32) -> Self {
+        Self {
+            translation: self.translation,
+            rotation: self.rotation,
+            scale,
+        }
+    }
+
+    #[inline]
+    pub fn apply(&self, pos: glam::Vec3) -> glam::Vec3 {
+        self.rotation * (pos * self.scale) + self.translation
+    }
+}
+
+impl Default for LatticeTransform {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+/// Mapping of the node and link IDs of one builder to the IDs of another.
+///
+/// The ID `i` of the source builder maps to `nodes[i]` or `links[i]`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatticeRemap {
+    pub nodes: Vec<u32>,
+    pub links: Vec<u32>,
+}
+
+impl LatticeRemap {
+    /// The ID of a node or link that no longer exists.
+    pub const REMOVED: u32 = u32::MAX;
+}
+
+/// The compliance of two links with compliances `a` and `b` acting in
+/// parallel.
+#[inline]
+fn parallel_compliance(a: f32, b: f32) -> f32 {
+    // stiffnesses add up; a rigid link keeps the pair rigid
+    if a == 0.0 || b == 0.0 {
+        0.0
+    } else {
+        1.0 / (1.0 / a + 1.0 / b)
+    }
+}
+
+/// A defect in the definition of a lattice.
+///
+/// Node and link IDs are the IDs handed out by the [`XpbdLatticeBuilder`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LatticeError {
+    /// A link was requested from the stack without enough nodes in it.
+    StackUnderflow,
+    /// A node ID does not refer to a node of the builder.
+    UnknownNode(u32),
+    /// A link ID does not refer to a link of the builder.
+    UnknownLink(u32),
+    /// A link connects a node to itself.
+    SelfLink(u32),
+    /// `link` connects the same two nodes as the earlier `duplicate_of`.
+    DuplicateLink { link: u32, duplicate_of: u32 },
+    /// A link has a rest length of zero, so it has no direction.
+    ZeroLengthLink(u32),
+    /// A node that is not fixed has no positive mass.
+    ZeroMassNode(u32),
+    /// A node is not connected to any link.
+    UnlinkedNode(u32),
+    /// A handle does not refer to a live node of the table.
+    UnknownHandle(u32),
+}
+
+impl std::fmt::Display for LatticeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::StackUnderflow => write!(f, "not enough nodes in the stack to create a link"),
+            Self::UnknownNode(node) => write!(f, "node {node} does not exist"),
+            Self::UnknownLink(link) => write!(f, "link {link} does not exist"),
+            Self::SelfLink(node) => write!(f, "cannot link node {node} to itself"),
+            Self::DuplicateLink { link, duplicate_of } => {
+                write!(f, "link {link} duplicates link {duplicate_of}")
+            }
+            Self::ZeroLengthLink(link) => write!(f, "link {link} has zero length"),
+            Self::ZeroMassNode(node) => write!(f, "node {node} is not fixed and has no mass"),
+            Self::UnlinkedNode(node) => write!(f, "node {node} has no links"),
+            Self::UnknownHandle(handle) => write!(f, "node handle {handle} is not live"),
+        }
+    }
+}
+
+impl std::error::Error for LatticeError {}
+
+#[derive(Clone, Debug, Default)]
+pub struct LatticeIds {
+    pub nodes: Vec<u32>,
+    pub links: Vec<u32>,
+    /// The groups of the lattice by name, resolved to table handles.
+    pub groups: BTreeMap<String, LatticeGroup>,
+    /// Handles of the live nodes the lattice was linked to.
+    ///
+    /// These are also part of `nodes`, but they are not owned by the
+    /// lattice; see [`XpbdLatticeBuilder::external`].
+    pub external: Vec<u32>,
+}
+
+impl LatticeIds {
+    /// Iterate over the handles of the nodes created by the lattice, leaving
+    /// out its external nodes.
+    pub fn owned_nodes(&self) -> impl Iterator<Item = u32> + '_ {
+        self.nodes
+            .iter()
+            .copied()
+            .filter(|node| !self.external.contains(node))
+    }
+
+    /// Get the handles of the nodes and links tagged with `name`.
+    pub fn group(&self, name: &str) -> Option<&LatticeGroup> {
+        self.groups.get(name)
+    }
+}
+
+/// A named set of nodes and links of a lattice.
+///
+/// In a [`XpbdLatticeBuilder`] these are node and link IDs; once exported,
+/// in [`LatticeIds`], these are table handles.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatticeGroup {
+    pub nodes: Vec<u32>,
+    pub links: Vec<u32>,
+}
+
+pub const DEFAULT_SOLVE_ITERATIONS: u32 = 8;
+pub const DEFAULT_SUB_STEPS: u32 = 4;
+pub const DAMPING: f32 = 0.996;
+
+ethel::table_spec! {
+    struct Nodes {
+        predicted_pos: glam::Vec3;
+        current_pos: glam::Vec3;
+        mass: f32;
+        inv_mass: f32;
+        forces: glam::Vec3;
+        velocity: glam::Vec3;
+        surface: Surface;
+        sleeping: bool;
     }
 }
 
@@ -304,9 +1186,42 @@ ethel::table_spec! {
         compliance: f32;
         rest_length: f32;
         lambda: f32;
+        limits: LinkLimits;
+        plasticity: Plasticity;
+        initial_length: f32;
     }
 }
 
+/// How the solver iterates over link constraints.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SolverMode {
+    /// Solve every link in table order, one after the other.
+    #[default]
+    GaussSeidel,
+
+    /// Solve links in batches of a [`ConstraintColouring`], where no two
+    /// links of a batch share a node.
+    ///
+    /// With the `parallel` feature, the links of a batch are solved across
+    /// threads.
+    Coloured,
+
+    /// Solve every link against the same predicted positions, then move
+    /// each node by the average of its corrections scaled by `relaxation`.
+    ///
+    /// Results do not depend on the order of links, at the cost of slower
+    /// convergence than [`SolverMode::GaussSeidel`].
+    Jacobi { relaxation: f32 },
+}
+
+pub const DEFAULT_JACOBI_RELAXATION: f32 = 1.5;
+
+impl SolverMode {
+    pub const JACOBI: Self = Self::Jacobi {
+        relaxation: DEFAULT_JACOBI_RELAXATION,
+    };
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct XpbdSolver {
     iterations: u32,
@@ -315,7 +1230,16 @@ pub struct XpbdSolver {
     h2: f32,
     allow_breaking: bool,
     ground_level: Option<f32>,
+    gravity: glam::Vec3,
     broken_links: Vec<u32>,
+    mode: SolverMode,
+    colouring: ConstraintColouring,
+
+    // per-node accumulators of the jacobi solver
+    jacobi_corrections: Vec<glam::Vec3>,
+    jacobi_counts: Vec<u32>,
+    // per-link lagrange multiplier steps of the jacobi solver
+    jacobi_lambdas: Vec<f32>,
 }
 
 impl Default for XpbdSolver {
@@ -326,8 +1250,14 @@ impl Default for XpbdSolver {
             h: 0.0,
             h2: 0.0,
             ground_level: None,
+            gravity: glam::Vec3::ZERO,
             allow_breaking: true,
             broken_links: Vec::with_capacity(32),
+            mode: SolverMode::default(),
+            colouring: ConstraintColouring::new(),
+            jacobi_corrections: Vec::new(),
+            jacobi_counts: Vec::new(),
+            jacobi_lambdas: Vec::new(),
         }
     }
 }
@@ -338,6 +1268,9 @@ pub struct XpbdOptions {
     pub substeps: u32,
     pub allow_breaking: bool,
     pub ground_level: Option<f32>,
+    pub mode: SolverMode,
+    /// Ambient acceleration of every free node, such as gravity and wind.
+    pub gravity: glam::Vec3,
 }
 
 impl XpbdOptions {
@@ -352,6 +1285,8 @@ impl XpbdOptions {
             substeps,
             allow_breaking,
             ground_level,
+            mode: SolverMode::GaussSeidel,
+            gravity: glam::Vec3::ZERO,
         }
     }
 
@@ -361,6 +1296,8 @@ impl XpbdOptions {
             substeps: self.substeps,
             allow_breaking: self.allow_breaking,
             ground_level: self.ground_level,
+            mode: self.mode,
+            gravity: self.gravity,
         }
     }
 
@@ -370,6 +1307,8 @@ impl XpbdOptions {
             iterations: self.iterations,
             allow_breaking: self.allow_breaking,
             ground_level: self.ground_level,
+            mode: self.mode,
+            gravity: self.gravity,
         }
     }
 
@@ -379,6 +1318,8 @@ impl XpbdOptions {
             iterations: self.iterations,
             substeps: self.substeps,
             ground_level: self.ground_level,
+            mode: self.mode,
+            gravity: self.gravity,
         }
     }
 
@@ -388,6 +1329,30 @@ impl XpbdOptions {
             iterations: self.iterations,
             substeps: self.substeps,
             allow_breaking: self.allow_breaking,
+            mode: self.mode,
+            gravity: self.gravity,
+        }
+    }
+
+    pub const fn with_mode(self, mode: SolverMode) -> Self {
+        Self {
+            mode,
+            iterations: self.iterations,
+            substeps: self.substeps,
+            allow_breaking: self.allow_breaking,
+            ground_level: self.ground_level,
+            gravity: self.gravity,
+        }
+    }
+
+    pub const fn with_gravity(self, gravity: glam::Vec3) -> Self {
+        Self {
+            gravity,
+            iterations: self.iterations,
+            substeps: self.substeps,
+            allow_breaking: self.allow_breaking,
+            ground_level: self.ground_level,
+            mode: self.mode,
         }
     }
 }
@@ -399,6 +1364,8 @@ impl Default for XpbdOptions {
             substeps: DEFAULT_SUB_STEPS,
             allow_breaking: true,
             ground_level: None,
+            mode: SolverMode::GaussSeidel,
+            gravity: glam::Vec3::ZERO,
         }
     }
 }
@@ -413,7 +1380,13 @@ impl XpbdSolver {
             substeps: options.substeps,
             allow_breaking: options.allow_breaking,
             ground_level: options.ground_level,
+            gravity: options.gravity,
             broken_links: Vec::with_capacity(32 * options.allow_breaking as usize),
+            mode: options.mode,
+            colouring: ConstraintColouring::new(),
+            jacobi_corrections: Vec::new(),
+            jacobi_counts: Vec::new(),
+            jacobi_lambdas: Vec::new(),
         }
     }
 
@@ -427,6 +1400,61 @@ impl XpbdSolver {
         self.substeps
     }
 
+    #[inline]
+    pub const fn allow_breaking(&self) -> bool {
+        self.allow_breaking
+    }
+
+    #[inline]
+    pub const fn mode(&self) -> SolverMode {
+        self.mode
+    }
+
+    #[inline]
+    pub const fn set_mode(&mut self, mode: SolverMode) {
+        self.mode = mode;
+    }
+
+    #[inline]
+    pub const fn gravity(&self) -> glam::Vec3 {
+        self.gravity
+    }
+
+    /// Set the ambient acceleration of every free node.
+    ///
+    /// Unlike forces accumulated on nodes, which are spent by the next step,
+    /// this acts on every substep: the motion it causes does not depend on
+    /// how often the solver is stepped.
+    #[inline]
+    pub const fn set_gravity(&mut self, gravity: glam::Vec3) {
+        self.gravity = gravity;
+    }
+
+    #[inline]
+    pub fn colouring(&self) -> &ConstraintColouring {
+        &self.colouring
+    }
+
+    /// Colour newly created links, such as the links of an exported lattice.
+    ///
+    /// Every link must be coloured before stepping in
+    /// [`SolverMode::Coloured`]. Links freed by the solver are removed from
+    /// the colouring automatically.
+    #[inline]
+    pub fn track_links(&mut self, handles: &[u32], links: &LinksRowTable) {
+        self.colouring.insert_links(handles, links);
+    }
+
+    /// Remove links freed outside of the solver from the colouring.
+    ///
+    /// Any of these links pending to be freed as broken are forgotten, so
+    /// their handles are never freed twice.
+    #[inline]
+    pub fn untrack_links(&mut self, handles: &[u32]) {
+        self.colouring.remove_links(handles);
+        self.broken_links.retain(|handle| !handles.contains(handle));
+    }
+
     #[inline]
     pub const fn set_iterations(&mut self, iterations: u32) {
         self.iterations = iterations;
@@ -439,12 +1467,19 @@ impl XpbdSolver {
 
     #[inline]
     pub const fn set_step_time(&mut self, delta: DeltaTime) {
-        self.h = delta.as_f32() / self.substeps as f32;
+        self.set_step_seconds(delta.as_f32());
+    }
+
+    #[inline]
+    pub const fn set_step_seconds(&mut self, seconds: f32) {
+        self.h = seconds / self.substeps as f32;
         self.h2 = self.h * self.h;
     }
 
     /// Break a link by its ID.
     ///
+    /// Breaking a link that is already pending to be freed does nothing.
+    ///
     /// # Panics
     /// Will panic:
     /// * If `link_id` is an invalid constraint handle.
@@ -455,7 +1490,9 @@ impl XpbdSolver {
             "cannot query broken links: allow_breaking flag for XPBD is set to false"
         );
 
-        self.broken_links.push(link_id);
+        if !self.broken_links.contains(&link_id) {
+            self.broken_links.push(link_id);
+        }
     }
 
     /// Returns a slice over the constraint IDs that were broken in the last
@@ -480,23 +1517,15 @@ impl XpbdSolver {
         self.broken_links.iter().for_each(|&handle| {
             links.free(handle);
         });
+        self.colouring.remove_links(&self.broken_links);
 
         // clear last frame, allow external systems to act from
         // accumulated broken links
         self.broken_links.clear();
 
+        self.yield_links(nodes, links);
         if self.allow_breaking {
-            const LAMBDA_STRAIN_THRESHOLD: f32 = 52_000.0;
-            const LAMBDA_COMPRESSION_THRESHOLD: f32 = -20_000.0;
-
-            for (handle, lambda) in links.handles().iter().zip(links.lambda_slice()) {
-                let force_strain = *lambda / self.h2;
-                if force_strain >= LAMBDA_STRAIN_THRESHOLD
-                    || force_strain <= LAMBDA_COMPRESSION_THRESHOLD
-                {
-                    self.broken_links.push(*handle);
-                }
-            }
+            self.find_broken_links(nodes, links);
         }
 
         for _ in 0..self.substeps {
@@ -507,6 +1536,64 @@ impl XpbdSolver {
         }
     }
 
+    /// Creep the rest length of every yielding plastic link toward its
+    /// current length.
+    #[inline]
+    fn yield_links(&self, nodes: &NodesRowTable, links: &mut LinksRowTable) {
+        let (rel, _, len, lambda, _, plasticity, initial) = links.split_mut();
+        let view = rel.join(len).join(lambda).join(plasticity).join(initial);
+
+        for (ab, l, y, plasticity, l0) in view {
+            if plasticity.is_elastic() {
+                continue;
+            }
+
+            let force = *y / self.h2;
+            if force.abs() <= plasticity.yield_force {
+                continue;
+            }
+
+            let dist = Self::link_length(nodes, ab);
+            let flow = (dist - *l) * plasticity.creep;
+            *l += flow;
+            plasticity.yield_force += plasticity.hardening * flow.abs() / *l0;
+        }
+    }
+
+    #[inline]
+    fn find_broken_links(&mut self, nodes: &NodesRowTable, links: &LinksRowTable) {
+        let handles = links.handles();
+        let relations = links.relation_slice();
+        let lambdas = links.lambda_slice();
+        let limits = links.limits_slice();
+        let plasticity = links.plasticity_slice();
+        let initial = links.initial_length_slice();
+
+        for i in 0..handles.len() {
+            let broken = if plasticity[i].is_elastic() {
+                let force = lambdas[i] / self.h2;
+                limits[i].exceeded_by(force)
+            } else {
+                let l0 = initial[i];
+                let strain = (Self::link_length(nodes, &relations[i]) - l0).abs() / l0;
+                strain >= plasticity[i].ultimate_strain
+            };
+
+            if broken {
+                self.broken_links.push(handles[i]);
+            }
+        }
+    }
+
+    #[inline]
+    fn link_length(nodes: &NodesRowTable, ab: &LinkNodes) -> f32 {
+        let i_a = unsafe { nodes.get_indirect_unchecked(ab.0) };
+        let i_b = unsafe { nodes.get_indirect_unchecked(ab.1) };
+        let positions = nodes.current_pos_slice();
+
+        positions[i_a as usize].distance(positions[i_b as usize])
+    }
+
     #[inline]
     fn substep(&mut self, nodes: &mut NodesRowTable, links: &mut LinksRowTable) {
         self.predict_positions(nodes);
@@ -527,91 +1614,596 @@ impl XpbdSolver {
         let c_pos = &nodes.current_pos;
         let inv_mass = &nodes.inv_mass;
         let velocity = &nodes.velocity;
+        let sleeping = &nodes.sleeping;
         let p_pos = &mut nodes.predicted_pos;
         let forces = &mut nodes.forces;
 
         for i in 0..node_count {
             let x = c_pos[i];
             let f = std::mem::take(&mut forces[i]);
+            if sleeping[i] {
+                p_pos[i] = x;
+                continue;
+            }
+
             let v = velocity[i];
             let w = inv_mass[i];
+            // fixed nodes do not fall
+            let g = if w > 0.0 {
+                self.gravity
+            } else {
+                glam::Vec3::ZERO
+            };
 
             let p = &mut p_pos[i];
 
-            *p = x + self.h * v + self.h2 * f * w;
+            *p = x + self.h * v + self.h2 * (f * w + g);
+        }
+    }
+
+    #[inline]
+    fn solve_constraints(&mut self, node_data: &mut NodesRowTable, link_data: &mut LinksRowTable) {
+        match self.mode {
+            SolverMode::GaussSeidel => self.solve_gauss_seidel(node_data, link_data),
+            SolverMode::Coloured => self.solve_coloured(node_data, link_data),
+            SolverMode::Jacobi { relaxation } => {
+                self.solve_jacobi(relaxation, node_data, link_data)
+            }
         }
     }
 
     #[inline]
-    fn solve_constraints(&self, node_data: &mut NodesRowTable, link_data: &mut LinksRowTable) {
-        let (rel, comp, len, lambda) = link_data.split_mut();
+    fn solve_gauss_seidel(&self, node_data: &mut NodesRowTable, link_data: &mut LinksRowTable) {
+        let (rel, comp, len, lambda, _, _, _) = link_data.split_mut();
         let view = rel.join(comp).join(len).join(lambda);
 
         for (ab, inv_stiffness, l, y) in view {
-            let i_a = unsafe { node_data.get_indirect_unchecked(ab.0) };
-            let i_b = unsafe { node_data.get_indirect_unchecked(ab.1) };
+            let i_a = unsafe { node_data.get_indirect_unchecked(ab.0) } as usize;
+            let i_b = unsafe { node_data.get_indirect_unchecked(ab.1) } as usize;
+            let sleeping = &node_data.sleeping;
+            if sleeping[i_a] && sleeping[i_b] {
+                continue;
+            }
+
             let inv_mass = &node_data.inv_mass;
             let position = &mut node_data.predicted_pos;
 
-            let w_a = inv_mass[i_a as usize];
-            let w_b = inv_mass[i_b as usize];
+            let w_a = inv_mass[i_a];
+            let w_b = inv_mass[i_b];
+
+            let p_a = position[i_a];
+            let p_b = position[i_b];
+
+            if let Some(correction) =
+                self.link_correction(p_a, p_b, w_a, w_b, *inv_stiffness, *l, y)
+            {
+                position[i_a] += w_a * correction;
+                position[i_b] -= w_b * correction;
+            }
+        }
+    }
+
+    #[inline]
+    fn solve_coloured(&self, node_data: &mut NodesRowTable, link_data: &mut LinksRowTable) {
+        for batch in self.colouring.batches() {
+            #[cfg(feature = "parallel")]
+            self.solve_batch_parallel(batch, node_data, link_data);
+            #[cfg(not(feature = "parallel"))]
+            self.solve_links(batch, node_data, link_data);
+        }
+
+        // overflow links may share nodes: never solve them in parallel
+        self.solve_links(self.colouring.overflow(), node_data, link_data);
+    }
+
+    #[inline]
+    fn solve_jacobi(
+        &mut self,
+        relaxation: f32,
+        node_data: &mut NodesRowTable,
+        link_data: &mut LinksRowTable,
+    ) {
+        let node_count = node_data.len();
+        let mut corrections = std::mem::take(&mut self.jacobi_corrections);
+        let mut counts = std::mem::take(&mut self.jacobi_counts);
+        let mut lambdas = std::mem::take(&mut self.jacobi_lambdas);
+        corrections.clear();
+        corrections.resize(node_count, glam::Vec3::ZERO);
+        counts.clear();
+        counts.resize(node_count, 0);
+        lambdas.clear();
+        lambdas.resize(link_data.len(), 0.0);
+
+        let (rel, comp, len, lambda, _, _, _) = link_data.split_mut();
+        let view = rel.join(comp).join(len).join(lambda).zip(&mut lambdas);
+
+        for ((ab, inv_stiffness, l, y), d_y) in view {
+            let i_a = unsafe { node_data.get_indirect_unchecked(ab.0) } as usize;
+            let i_b = unsafe { node_data.get_indirect_unchecked(ab.1) } as usize;
+            let sleeping = &node_data.sleeping;
+            if sleeping[i_a] && sleeping[i_b] {
+                continue;
+            }
+
+            let inv_mass = &node_data.inv_mass;
+            let position = &node_data.predicted_pos;
+
+            let w_a = inv_mass[i_a];
+            let w_b = inv_mass[i_b];
+
+            let p_a = position[i_a];
+            let p_b = position[i_b];
+
+            // lambda only moves once the corrections are averaged
+            let mut y_next = *y;
+            if let Some(correction) =
+                self.link_correction(p_a, p_b, w_a, w_b, *inv_stiffness, *l, &mut y_next)
+            {
+                corrections[i_a] += w_a * correction;
+                corrections[i_b] -= w_b * correction;
+                counts[i_a] += 1;
+                counts[i_b] += 1;
+                *d_y = y_next - *y;
+            }
+        }
 
-            let p_a = position[i_a as usize];
-            let p_b = position[i_b as usize];
+        let positions = node_data.predicted_pos_mut_slice();
+        for ((p, correction), &count) in positions.iter_mut().zip(&corrections).zip(&counts) {
+            if count > 0 {
+                *p += *correction * (relaxation / count as f32);
+            }
+        }
 
-            let ab_d = p_a - p_b;
-            let dist = ab_d.length();
-            if dist < 0.1e-6 {
+        // scale each lambda step like the corrections of its nodes were, so
+        // it stays in line with how far the link actually moved
+        let inv_mass = node_data.inv_mass_slice();
+        let (rel, _, _, lambda, _, _, _) = link_data.split_mut();
+        for ((ab, y), &d_y) in rel.join(lambda).zip(&lambdas) {
+            if d_y == 0.0 {
                 continue;
             }
+            let i_a = unsafe { node_data.get_indirect_unchecked(ab.0) } as usize;
+            let i_b = unsafe { node_data.get_indirect_unchecked(ab.1) } as usize;
 
-            let compliance = *inv_stiffness / self.h2;
+            let w_a = inv_mass[i_a];
+            let w_b = inv_mass[i_b];
+            let s_a = relaxation / counts[i_a] as f32;
+            let s_b = relaxation / counts[i_b] as f32;
 
-            let w_t = w_a + w_b;
-            if w_t < 0.1e-6 {
+            *y += d_y * (w_a * s_a + w_b * s_b) / (w_a + w_b);
+        }
+
+        self.jacobi_corrections = corrections;
+        self.jacobi_counts = counts;
+        self.jacobi_lambdas = lambdas;
+    }
+
+    /// Solve the links `handles` one after the other.
+    #[inline]
+    fn solve_links(
+        &self,
+        handles: &[u32],
+        node_data: &mut NodesRowTable,
+        link_data: &mut LinksRowTable,
+    ) {
+        for &handle in handles {
+            let link = unsafe { link_data.get_indirect_unchecked(handle) } as usize;
+            let ab = link_data.relation_slice()[link];
+            let inv_stiffness = link_data.compliance_slice()[link];
+            let l = link_data.rest_length_slice()[link];
+
+            let i_a = unsafe { node_data.get_indirect_unchecked(ab.0) } as usize;
+            let i_b = unsafe { node_data.get_indirect_unchecked(ab.1) } as usize;
+            if node_data.sleeping_slice()[i_a] && node_data.sleeping_slice()[i_b] {
                 continue;
             }
 
-            let constraint = dist - *l;
-            let d_y = (-constraint - compliance * *y) / (w_a + w_b + compliance);
-            *y += d_y;
+            let w_a = node_data.inv_mass_slice()[i_a];
+            let w_b = node_data.inv_mass_slice()[i_b];
+            let position = node_data.predicted_pos_mut_slice();
+            let y = &mut link_data.lambda_mut_slice()[link];
 
-            let gradient = ab_d / dist;
-            position[i_a as usize] += w_a * d_y * gradient;
-            position[i_b as usize] -= w_b * d_y * gradient;
+            if let Some(correction) =
+                self.link_correction(position[i_a], position[i_b], w_a, w_b, inv_stiffness, l, y)
+            {
+                position[i_a] += w_a * correction;
+                position[i_b] -= w_b * correction;
+            }
         }
     }
 
+    /// Solve all links of a colour `batch`.
+    ///
+    /// Links of the same batch never share a node, so they are solved in
+    /// parallel.
+    #[cfg(feature = "parallel")]
     #[inline]
-    fn apply_ground_constraint(&self, node_data: &mut NodesRowTable) {
-        const RESTITUTION: f32 = 0.4;
-        const FRICTION: f32 = 0.2;
+    fn solve_batch_parallel(
+        &self,
+        batch: &[u32],
+        node_data: &mut NodesRowTable,
+        link_data: &mut LinksRowTable,
+    ) {
+        use rayon::prelude::*;
+
+        let positions = SharedMut(node_data.predicted_pos_mut_slice().as_mut_ptr());
+        let lambdas = SharedMut(link_data.lambda_mut_slice().as_mut_ptr());
+        let node_data = &*node_data;
+        let link_data = &*link_data;
+
+        batch.par_iter().for_each(|&handle| {
+            let link = unsafe { link_data.get_indirect_unchecked(handle) } as usize;
+            let ab = link_data.relation_slice()[link];
+            let inv_stiffness = link_data.compliance_slice()[link];
+            let l = link_data.rest_length_slice()[link];
+
+            let i_a = unsafe { node_data.get_indirect_unchecked(ab.0) } as usize;
+            let i_b = unsafe { node_data.get_indirect_unchecked(ab.1) } as usize;
+            if node_data.sleeping_slice()[i_a] && node_data.sleeping_slice()[i_b] {
+                return;
+            }
 
+            let w_a = node_data.inv_mass_slice()[i_a];
+            let w_b = node_data.inv_mass_slice()[i_b];
+
+            // SAFETY: every link of a batch touches nodes that no other link
+            // of the same batch touches, and every link appears once; no two
+            // threads access the same position or lambda.
+            unsafe {
+                let p_a = positions.get().add(i_a);
+                let p_b = positions.get().add(i_b);
+                let y = &mut *lambdas.get().add(link);
+
+                if let Some(correction) =
+                    self.link_correction(*p_a, *p_b, w_a, w_b, inv_stiffness, l, y)
+                {
+                    *p_a += w_a * correction;
+                    *p_b -= w_b * correction;
+                }
+            }
+        });
+    }
+
+    /// Solve the distance constraint between `p_a` and `p_b`, accumulating
+    /// the lagrange multiplier into `lambda`.
+    ///
+    /// Returns the correction along the constraint gradient, before scaling
+    /// by the inverse masses: `p_a` moves by `w_a * correction`, `p_b` by
+    /// `-w_b * correction`.
+    #[inline]
+    #[allow(clippy::too_many_arguments)]
+    fn link_correction(
+        &self,
+        p_a: glam::Vec3,
+        p_b: glam::Vec3,
+        w_a: f32,
+        w_b: f32,
+        inv_stiffness: f32,
+        rest_length: f32,
+        lambda: &mut f32,
+    ) -> Option<glam::Vec3> {
+        let ab_d = p_a - p_b;
+        let dist = ab_d.length();
+        if dist < 0.1e-6 {
+            return None;
+        }
+
+        let compliance = inv_stiffness / self.h2;
+
+        let w_t = w_a + w_b;
+        if w_t < 0.1e-6 {
+            return None;
+        }
+
+        let constraint = dist - rest_length;
+        let d_y = (-constraint - compliance * *lambda) / (w_t + compliance);
+        *lambda += d_y;
+
+        let gradient = ab_d / dist;
+        Some(d_y * gradient)
+    }
+
+    #[inline]
+    fn apply_ground_constraint(&self, node_data: &mut NodesRowTable) {
         let ground_level = self.ground_level.unwrap_or_default();
-        let (n_pos, c_pos, _, _, _, velocity) = node_data.split_mut();
-        for (n_pos, c_pos, vel) in n_pos.join(c_pos).join(velocity) {
+        let (n_pos, c_pos, _, _, _, velocity, surface, _) = node_data.split_mut();
+        for (n_pos, c_pos, vel, surface) in n_pos.join(c_pos).join(velocity).join(surface) {
             if n_pos.y < ground_level {
                 n_pos.y = ground_level;
                 c_pos.y = ground_level;
 
-                vel.y *= -RESTITUTION;
-                vel.x *= FRICTION;
-                vel.z *= FRICTION;
+                let retained = 1.0 - surface.friction;
+                vel.y *= -surface.restitution;
+                vel.x *= retained;
+                vel.z *= retained;
             }
         }
     }
 
     #[inline]
     fn finalise_nodes(&self, node_data: &mut NodesRowTable) {
-        let (p_pos, c_pos, _, _, _, vel) = node_data.split_mut();
+        let (p_pos, c_pos, _, _, _, vel, _, sleeping) = node_data.split_mut();
 
-        for (p, x, v) in p_pos.join(c_pos).join(vel) {
+        for (p, x, v, sleeping) in p_pos.join(c_pos).join(vel).join(sleeping) {
+            if *sleeping {
+                continue;
+            }
             *v = (*p - *x) / self.h;
             *x = *p;
         }
     }
 }
 
+impl Snapshot for LinkNodes {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.0);
+        writer.write(&self.1);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self(reader.read()?, reader.read()?))
+    }
+}
+
+impl Snapshot for LinkLimits {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.tension);
+        writer.write(&self.compression);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self::new(reader.read()?, reader.read()?))
+    }
+}
+
+impl Snapshot for Plasticity {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.yield_force);
+        writer.write(&self.creep);
+        writer.write(&self.hardening);
+        writer.write(&self.ultimate_strain);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self::new(
+            reader.read()?,
+            reader.read()?,
+            reader.read()?,
+            reader.read()?,
+        ))
+    }
+}
+
+impl Snapshot for XpbdNodeOptions {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.pos);
+        writer.write(&self.mass);
+        writer.write(&self.fixed);
+        writer.write(&self.surface);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self {
+            pos: reader.read()?,
+            mass: reader.read()?,
+            fixed: reader.read()?,
+            surface: reader.read()?,
+        })
+    }
+}
+
+impl Snapshot for XpbdLinkOptions {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.compliance);
+        writer.write(&self.rest_length);
+        writer.write(&self.limits);
+        writer.write(&self.plasticity);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self {
+            compliance: reader.read()?,
+            rest_length: reader.read()?,
+            limits: reader.read()?,
+            plasticity: reader.read()?,
+        })
+    }
+}
+
+impl Snapshot for NodesRowTable {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&HandleMaps::new(
+            self.handles(),
+            self.slots_map(),
+            self.free_list(),
+        ));
+        writer.write_slice(self.predicted_pos_slice());
+        writer.write_slice(self.current_pos_slice());
+        writer.write_slice(self.mass_slice());
+        writer.write_slice(self.inv_mass_slice());
+        writer.write_slice(self.forces_slice());
+        writer.write_slice(self.velocity_slice());
+        writer.write_slice(self.surface_slice());
+        writer.write_slice(self.sleeping_slice());
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        let maps = reader.read::<HandleMaps>()?;
+        let predicted_pos = reader.read_vec::<glam::Vec3>()?;
+        let current_pos = reader.read_vec::<glam::Vec3>()?;
+        let mass = reader.read_vec::<f32>()?;
+        let inv_mass = reader.read_vec::<f32>()?;
+        let forces = reader.read_vec::<glam::Vec3>()?;
+        let velocity = reader.read_vec::<glam::Vec3>()?;
+        let surface = reader.read_vec::<Surface>()?;
+        let sleeping = reader.read_vec::<bool>()?;
+
+        maps.check_column(&predicted_pos)?;
+        maps.check_column(&current_pos)?;
+        maps.check_column(&mass)?;
+        maps.check_column(&inv_mass)?;
+        maps.check_column(&forces)?;
+        maps.check_column(&velocity)?;
+        maps.check_column(&surface)?;
+        maps.check_column(&sleeping)?;
+
+        // skip degenerate; the fresh table has its own
+        let mut table = Self::with_capacity(maps.len());
+        for i in 1..maps.len() {
+            table.put((
+                predicted_pos[i],
+                current_pos[i],
+                mass[i],
+                inv_mass[i],
+                forces[i],
+                velocity[i],
+                surface[i],
+                sleeping[i],
+            ));
+        }
+        let (handles, slots_map, free_list) = maps.into_parts();
+        table.handles_mut().copy_from_slice(&handles);
+        *table.slots_map_mut() = slots_map;
+        *table.free_list_mut() = free_list;
+        Ok(table)
+    }
+}
+
+impl Snapshot for LinksRowTable {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&HandleMaps::new(
+            self.handles(),
+            self.slots_map(),
+            self.free_list(),
+        ));
+        writer.write_slice(self.relation_slice());
+        writer.write_slice(self.compliance_slice());
+        writer.write_slice(self.rest_length_slice());
+        writer.write_slice(self.lambda_slice());
+        writer.write_slice(self.limits_slice());
+        writer.write_slice(self.plasticity_slice());
+        writer.write_slice(self.initial_length_slice());
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        let maps = reader.read::<HandleMaps>()?;
+        let relation = reader.read_vec::<LinkNodes>()?;
+        let compliance = reader.read_vec::<f32>()?;
+        let rest_length = reader.read_vec::<f32>()?;
+        let lambda = reader.read_vec::<f32>()?;
+        let limits = reader.read_vec::<LinkLimits>()?;
+        let plasticity = reader.read_vec::<Plasticity>()?;
+        let initial_length = reader.read_vec::<f32>()?;
+
+        maps.check_column(&relation)?;
+        maps.check_column(&compliance)?;
+        maps.check_column(&rest_length)?;
+        maps.check_column(&lambda)?;
+        maps.check_column(&limits)?;
+        maps.check_column(&plasticity)?;
+        maps.check_column(&initial_length)?;
+
+        // skip degenerate; the fresh table has its own
+        let mut table = Self::with_capacity(maps.len());
+        for i in 1..maps.len() {
+            table.put((
+                relation[i],
+                compliance[i],
+                rest_length[i],
+                lambda[i],
+                limits[i],
+                plasticity[i],
+                initial_length[i],
+            ));
+        }
+        let (handles, slots_map, free_list) = maps.into_parts();
+        table.handles_mut().copy_from_slice(&handles);
+        *table.slots_map_mut() = slots_map;
+        *table.free_list_mut() = free_list;
+        Ok(table)
+    }
+}
+
+impl Snapshot for SolverMode {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        match *self {
+            Self::GaussSeidel => writer.write(&0u8),
+            Self::Coloured => writer.write(&1u8),
+            Self::Jacobi { relaxation } => {
+                writer.write(&2u8);
+                writer.write(&relaxation);
+            }
+        }
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        match reader.read::<u8>()? {
+            0 => Ok(Self::GaussSeidel),
+            1 => Ok(Self::Coloured),
+            2 => Ok(Self::Jacobi {
+                relaxation: reader.read()?,
+            }),
+            _ => Err(SnapshotError::Corrupt("invalid solver mode")),
+        }
+    }
+}
+
+/// The solver settings, colouring and links pending to be freed.
+///
+/// Per-step scratch buffers are not saved.
+impl Snapshot for XpbdSolver {
+    fn save(&self, writer: &mut SnapshotWriter) {
+        writer.write(&self.iterations);
+        writer.write(&self.substeps);
+        writer.write(&self.h);
+        writer.write(&self.h2);
+        writer.write(&self.allow_breaking);
+        writer.write(&self.ground_level);
+        writer.write(&self.gravity);
+        writer.write(&self.broken_links);
+        writer.write(&self.mode);
+        writer.write(&self.colouring);
+    }
+
+    fn restore(reader: &mut SnapshotReader) -> Result<Self, SnapshotError> {
+        Ok(Self {
+            iterations: reader.read()?,
+            substeps: reader.read()?,
+            h: reader.read()?,
+            h2: reader.read()?,
+            allow_breaking: reader.read()?,
+            ground_level: reader.read()?,
+            gravity: reader.read()?,
+            broken_links: reader.read()?,
+            mode: reader.read()?,
+            colouring: reader.read()?,
+            jacobi_corrections: Vec::new(),
+            jacobi_counts: Vec::new(),
+            jacobi_lambdas: Vec::new(),
+        })
+    }
+}
+
+/// Raw pointer shared across the threads solving a colour batch.
+#[cfg(feature = "parallel")]
+#[derive(Clone, Copy)]
+struct SharedMut<T>(*mut T);
+
+#[cfg(feature = "parallel")]
+impl<T> SharedMut<T> {
+    // accessing through a method makes closures capture the whole wrapper,
+    // rather than the bare pointer field
+    #[inline]
+    fn get(&self) -> *mut T {
+        self.0
+    }
+}
+
+#[cfg(feature = "parallel")]
+unsafe impl<T> Send for SharedMut<T> {}
+#[cfg(feature = "parallel")]
+unsafe impl<T> Sync for SharedMut<T> {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -738,4 +2330,436 @@ mod tests {
             assert_eq!(link_ids, compare);
         }
     }
+
+    #[test]
+    fn xpbd_lattice_builder_link_limits() {
+        let mut builder = XpbdLatticeBuilder::new();
+
+        const NODE: XpbdNodeOptions = XpbdNodeOptions::new(glam::Vec3::ONE, 5.0);
+        const DEFAULT_LINK: XpbdLinkOptions = XpbdLinkOptions::new(1.0);
+        const WEAK_LINK: XpbdLinkOptions = XpbdLinkOptions::new(1.0).and_limits(10.0, 20.0);
+
+        let a = builder.node(NODE);
+        let b = builder.node(NODE);
+        let c = builder.node(NODE);
+        builder.link_nodes(a, b, DEFAULT_LINK);
+        builder.link_nodes(b, c, WEAK_LINK);
+
+        let mut nodes = NodesRowTable::new();
+        let mut links = LinksRowTable::new();
+
+        let map = builder.export(&mut nodes, &mut links);
+
+        let limits_of = |handle: u32| {
+            let index = links.get_indirect(handle).unwrap();
+            links.limits_slice()[index as usize]
+        };
+        assert_eq!(limits_of(map.links[0]), LinkLimits::DEFAULT);
+        assert_eq!(limits_of(map.links[1]), LinkLimits::new(10.0, 20.0));
+
+        assert!(LinkLimits::new(10.0, 20.0).exceeded_by(-10.0));
+        assert!(LinkLimits::new(10.0, 20.0).exceeded_by(20.0));
+        assert!(!LinkLimits::new(10.0, 20.0).exceeded_by(-9.0));
+        assert!(!LinkLimits::new(10.0, 20.0).exceeded_by(19.0));
+    }
+
+    #[test]
+    fn xpbd_lattice_builder_validate() {
+        const NODE: XpbdNodeOptions = XpbdNodeOptions::new(glam::Vec3::ONE, 1.0);
+        const LINK: XpbdLinkOptions = XpbdLinkOptions::new(1.0);
+
+        let mut builder = XpbdLatticeBuilder::new();
+        assert_eq!(builder.try_link(LINK), Err(LatticeError::StackUnderflow));
+
+        let a = builder.node(NODE);
+        let b = builder.node(NODE.with_fixed(true));
+        assert_eq!(builder.try_link_to(b, LINK), Err(LatticeError::SelfLink(b)));
+        assert_eq!(
+            builder.try_link_nodes(a, 9, LINK),
+            Err(LatticeError::UnknownNode(9))
+        );
+
+        // coincident nodes: zero length without an explicit rest length
+        let ab = builder.try_link(LINK).unwrap();
+        let ba = builder.link_nodes(b, a, LINK.and_rest_length(1.0));
+        let c = builder.node(XpbdNodeOptions::new(glam::Vec3::ZERO, 0.0));
+
+        assert_eq!(
+            builder.validate(),
+            Err(vec![
+                LatticeError::ZeroMassNode(c),
+                LatticeError::UnlinkedNode(c),
+                LatticeError::ZeroLengthLink(ab),
+                LatticeError::DuplicateLink {
+                    link: ba,
+                    duplicate_of: ab,
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn xpbd_lattice_builder_append() {
+        const LINK: XpbdLinkOptions = XpbdLinkOptions::with_rest_length(1.0, 2.0);
+
+        let mut floor = XpbdLatticeBuilder::new();
+        floor.node(XpbdNodeOptions::new(glam::Vec3::ZERO, 1.0));
+        floor.node(XpbdNodeOptions::new(glam::Vec3::X, 1.0));
+        floor.link(LINK);
+
+        let mut tower = XpbdLatticeBuilder::new();
+        let ground = tower.append(&floor, LatticeTransform::IDENTITY);
+        let transform = LatticeTransform::from_translation(glam::Vec3::Y)
+            .with_rotation(glam::Quat::from_rotation_y(std::f32::consts::FRAC_PI_2))
+            .with_scale(2.0);
+        let first = tower.append(&floor, transform);
+
+        assert_eq!(ground.nodes, [0, 1]);
+        assert_eq!(first.nodes, [2, 3]);
+        assert_eq!(first.links, [1]);
+
+        let pos = tower.nodes()[first.nodes[1] as usize].pos();
+        assert!(pos.abs_diff_eq(glam::vec3(0.0, 1.0, -2.0), 1e-5));
+
+        let (a, b, link) = tower.links().nth(first.links[0] as usize).unwrap();
+        assert_eq!((a, b), (first.nodes[0], first.nodes[1]));
+        assert_eq!(link.rest_length(), Some(4.0));
+
+        // cross-link the sub-assemblies
+        tower.link_nodes(ground.nodes[0], first.nodes[0], LINK);
+        assert_eq!(tower.validate(), Ok(()));
+    }
+
+    #[test]
+    fn xpbd_lattice_builder_groups() {
+        const NODE: XpbdNodeOptions = XpbdNodeOptions::new(glam::Vec3::ONE, 1.0);
+        const LINK: XpbdLinkOptions = XpbdLinkOptions::new(1.0);
+
+        let mut floor = XpbdLatticeBuilder::new();
+        let a = floor.node(NODE);
+        let b = floor.node(NODE);
+        let ab = floor.link(LINK);
+        floor.tag_links("pillars", &[ab]);
+        assert_eq!(
+            floor.try_tag_nodes("pillars", &[b, 7]),
+            Err(LatticeError::UnknownNode(7))
+        );
+
+        let mut tower = XpbdLatticeBuilder::new();
+        let ground = tower.append(&floor, LatticeTransform::IDENTITY);
+        let first = tower.append(&floor, LatticeTransform::from_translation(glam::Vec3::Y));
+        tower.tag_nodes("anchors", &[ground.nodes[a as usize]]);
+        tower.tag_nodes("floor:1", &first.nodes);
+
+        let mut nodes = NodesRowTable::new();
+        let mut links = LinksRowTable::new();
+        let map = tower.export(&mut nodes, &mut links);
+
+        assert_eq!(map.group("anchors").unwrap().nodes, [map.nodes[0]]);
+        assert_eq!(map.group("floor:1").unwrap().nodes, map.nodes[2..]);
+        assert_eq!(map.group("pillars").unwrap().links, map.links);
+        assert!(map.group("braces").is_none());
+    }
+
+    #[test]
+    fn xpbd_lattice_builder_weld() {
+        const NODE: XpbdNodeOptions = XpbdNodeOptions::new(glam::Vec3::ZERO, 1.0);
+        const LINK: XpbdLinkOptions = XpbdLinkOptions::new(2.0).and_limits(10.0, 20.0);
+
+        // two modules sharing the edge x = 1
+        let mut module = XpbdLatticeBuilder::new();
+        module.node(NODE);
+        module.node(XpbdNodeOptions::new(glam::Vec3::X, 1.0));
+        let edge = module.link(LINK);
+        module.tag_links("edge", &[edge]);
+
+        let mut builder = XpbdLatticeBuilder::new();
+        builder.append(&module, LatticeTransform::IDENTITY);
+        let shifted = LatticeTransform::from_translation(glam::vec3(1.0, 0.0, 0.001));
+        builder.append(&module, shifted);
+        // collapses into a self-link once welded
+        builder.link_nodes(1, 2, LINK);
+
+        let remap = builder.weld(0.01);
+        assert_eq!(remap.nodes, [0, 1, 1, 2]);
+        assert_eq!(remap.links, [0, 1, LatticeRemap::REMOVED]);
+        assert_eq!(builder.nodes().len(), 3);
+        assert_eq!(builder.nodes()[1].mass(), 2.0);
+        assert_eq!(builder.group("edge").unwrap().links, [0, 1]);
+
+        // a duplicate of link 0 acts in parallel with it
+        builder.link_nodes(1, 0, LINK);
+        let remap = builder.weld(0.01);
+        assert_eq!(remap.links, [0, 1, 0]);
+        let (_, _, link) = builder.links().next().unwrap();
+        assert_eq!(link.compliance(), 1.0);
+        assert_eq!(link.limits(), LinkLimits::new(20.0, 40.0));
+    }
+
+    #[test]
+    fn xpbd_lattice_builder_external() {
+        const LINK: XpbdLinkOptions = XpbdLinkOptions::new(1.0);
+
+        let mut base = XpbdLatticeBuilder::new();
+        base.node(XpbdNodeOptions::new(glam::Vec3::ZERO, 1.0).with_fixed(true));
+        base.node(XpbdNodeOptions::new(glam::Vec3::Y, 1.0));
+        base.link(LINK);
+
+        let mut nodes = NodesRowTable::new();
+        let mut links = LinksRowTable::new();
+        let base = base.export(&mut nodes, &mut links);
+        let top = base.nodes[1];
+
+        let mut extension = XpbdLatticeBuilder::new();
+        assert_eq!(
+            extension.try_external(42, &nodes),
+            Err(LatticeError::UnknownHandle(42))
+        );
+        let live = extension.external(top, &nodes);
+        assert_eq!(extension.external_handle(live), Some(top));
+        assert_eq!(extension.nodes()[live as usize].pos(), glam::Vec3::Y);
+
+        extension.node(XpbdNodeOptions::new(glam::vec3(0.0, 3.0, 0.0), 1.0));
+        extension.link(LINK);
+        // welds onto the live node, which is left untouched
+        extension.node(XpbdNodeOptions::new(glam::Vec3::Y, 5.0));
+        let remap = extension.weld(0.01);
+        assert_eq!(remap.nodes, [0, 1, 0]);
+        assert_eq!(extension.nodes()[0].mass(), 1.0);
+        assert_eq!(extension.validate(), Ok(()));
+
+        let node_count = nodes.len();
+        let ids = extension.export(&mut nodes, &mut links);
+        assert_eq!(nodes.len(), node_count + 1);
+        assert_eq!(ids.nodes[0], top);
+        assert_eq!(ids.external, [top]);
+        assert_eq!(ids.owned_nodes().collect::<Vec<_>>(), [ids.nodes[1]]);
+
+        let index = links.get_indirect(ids.links[0]).unwrap() as usize;
+        assert_eq!(links.relation_slice()[index], LinkNodes(top, ids.nodes[1]));
+        assert_eq!(links.rest_length_slice()[index], 2.0);
+    }
+
+    #[test]
+    fn xpbd_snapshot_keeps_handles() {
+        const NODE: XpbdNodeOptions = XpbdNodeOptions::new(glam::Vec3::ONE, 1.0);
+        const LINK: XpbdLinkOptions = XpbdLinkOptions::with_rest_length(1.0, 2.0);
+
+        let mut builder = XpbdLatticeBuilder::new();
+        builder.node(NODE);
+        builder.node(NODE);
+        builder.link(LINK);
+        builder.node(NODE);
+        builder.link(LINK);
+        builder.node(NODE);
+        builder.link(LINK);
+
+        let mut nodes = NodesRowTable::new();
+        let mut links = LinksRowTable::new();
+        let map = builder.export(&mut nodes, &mut links);
+
+        // leave a hole in the free list
+        links.free(map.links[1]);
+
+        let mut solver = XpbdSolver::default();
+        solver.set_mode(SolverMode::JACOBI);
+        solver.break_link(map.links[2]);
+
+        let mut writer = SnapshotWriter::new(1);
+        writer.write(&nodes);
+        writer.write(&links);
+        writer.write(&solver);
+        let bytes = writer.finish();
+
+        let mut reader = SnapshotReader::new(&bytes).unwrap();
+        let r_nodes = reader.read::<NodesRowTable>().unwrap();
+        let r_links = reader.read::<LinksRowTable>().unwrap();
+        let r_solver = reader.read::<XpbdSolver>().unwrap();
+        assert!(reader.is_empty());
+
+        assert_eq!(r_nodes.handles(), nodes.handles());
+        assert_eq!(r_nodes.current_pos_slice(), nodes.current_pos_slice());
+        assert_eq!(r_links.handles(), links.handles());
+        assert_eq!(r_links.slots_map(), links.slots_map());
+        assert_eq!(r_links.free_list(), links.free_list());
+        assert_eq!(r_links.get_indirect(map.links[1]), None);
+        assert_eq!(
+            r_links.get_indirect(map.links[2]),
+            links.get_indirect(map.links[2])
+        );
+        assert_eq!(r_solver, solver);
+    }
+
+    /// Export a link from a fixed node to a free one, one unit apart along
+    /// the X axis.
+    fn pulled_link(link: XpbdLinkOptions) -> (NodesRowTable, LinksRowTable, LatticeIds) {
+        let mut builder = XpbdLatticeBuilder::new();
+        builder.node(XpbdNodeOptions::new(glam::Vec3::ZERO, 1.0).with_fixed(true));
+        builder.node(XpbdNodeOptions::new(glam::Vec3::X, 1.0));
+        builder.link(link);
+
+        let mut nodes = NodesRowTable::new();
+        let mut links = LinksRowTable::new();
+        let map = builder.export(&mut nodes, &mut links);
+        (nodes, links, map)
+    }
+
+    /// Pull the free node of a [`pulled_link`] by `force` for `steps` steps.
+    ///
+    /// Returns `true` as soon as the link breaks.
+    fn pull(
+        solver: &mut XpbdSolver,
+        (nodes, links, map): &mut (NodesRowTable, LinksRowTable, LatticeIds),
+        force: f32,
+        steps: usize,
+    ) -> bool {
+        let free = nodes.get_indirect(map.nodes[1]).unwrap() as usize;
+        for _ in 0..steps {
+            nodes.forces_mut_slice()[free] = glam::vec3(force, 0.0, 0.0);
+            solver.step(nodes, links);
+            if solver.broken_links().contains(&map.links[0]) {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn stepped_solver() -> XpbdSolver {
+        let mut solver = XpbdSolver::default();
+        solver.set_step_seconds(1.0 / 60.0);
+        solver
+    }
+
+    #[test]
+    fn xpbd_plastic_link_creeps_past_yield() {
+        const PLASTICITY: Plasticity = Plasticity::new(10.0, 0.5, 0.0, 100.0);
+        const LINK: XpbdLinkOptions = XpbdLinkOptions::new(1e-3).and_plasticity(PLASTICITY);
+
+        // under the yield force, the link stays elastic
+        let mut lattice = pulled_link(LINK);
+        assert!(!pull(&mut stepped_solver(), &mut lattice, 1.0, 120));
+        assert_eq!(lattice.1.rest_length_slice()[1], 1.0);
+
+        let mut lattice = pulled_link(LINK);
+        assert!(!pull(&mut stepped_solver(), &mut lattice, 1000.0, 120));
+
+        let links = &lattice.1;
+        assert!(links.rest_length_slice()[1] > 1.01);
+        assert_eq!(links.initial_length_slice()[1], 1.0);
+        assert_eq!(links.plasticity_slice()[1], PLASTICITY);
+    }
+
+    #[test]
+    fn xpbd_plastic_link_hardens() {
+        const PLASTICITY: Plasticity = Plasticity::new(10.0, 0.5, 1000.0, 100.0);
+        const LINK: XpbdLinkOptions = XpbdLinkOptions::new(1e-3).and_plasticity(PLASTICITY);
+
+        let mut lattice = pulled_link(LINK);
+        assert!(!pull(&mut stepped_solver(), &mut lattice, 1000.0, 120));
+
+        let plasticity = lattice.1.plasticity_slice()[1];
+        assert!(plasticity.yield_force > PLASTICITY.yield_force);
+    }
+
+    #[test]
+    fn xpbd_plastic_link_breaks() {
+        // at its ultimate strain
+        const PLASTICITY: Plasticity = Plasticity::new(10.0, 0.5, 0.0, 0.05);
+        const LINK: XpbdLinkOptions = XpbdLinkOptions::new(1e-3).and_plasticity(PLASTICITY);
+
+        let mut lattice = pulled_link(LINK);
+        assert!(pull(&mut stepped_solver(), &mut lattice, 1000.0, 600));
+
+        // never on its limits, even far past them
+        const STIFF: Plasticity = Plasticity::new(1e9, 0.5, 0.0, 100.0);
+        const WEAK_LINK: XpbdLinkOptions = XpbdLinkOptions::new(1e-3)
+            .and_limits(10.0, 10.0)
+            .and_plasticity(STIFF);
+
+        let mut lattice = pulled_link(WEAK_LINK);
+        assert!(!pull(&mut stepped_solver(), &mut lattice, 1000.0, 120));
+        assert_eq!(lattice.1.rest_length_slice()[1], 1.0);
+    }
+
+    /// Let a truss hang from its top row under a constant load until it
+    /// settles, and return the positions of its nodes.
+    ///
+    /// Links are put in the reverse order if `reversed`.
+    fn settle_truss(mode: SolverMode, reversed: bool) -> Vec<glam::Vec3> {
+        const SIZE: usize = 4;
+        const LINK: XpbdLinkOptions = XpbdLinkOptions::new(1e-4);
+
+        let mut builder = XpbdLatticeBuilder::new();
+        let mut ids = Vec::new();
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let position = glam::vec3(x as f32, -(y as f32), 0.0);
+                let node = XpbdNodeOptions::new(position, 1.0).with_fixed(y == 0);
+                ids.push(builder.node(node));
+            }
+        }
+        let mut pairs = Vec::new();
+        for y in 0..SIZE {
+            for x in 0..SIZE {
+                let id = ids[y * SIZE + x];
+                if x + 1 < SIZE {
+                    pairs.push((id, ids[y * SIZE + x + 1]));
+                }
+                if y + 1 < SIZE {
+                    pairs.push((id, ids[(y + 1) * SIZE + x]));
+                }
+                if x + 1 < SIZE && y + 1 < SIZE {
+                    pairs.push((id, ids[(y + 1) * SIZE + x + 1]));
+                }
+            }
+        }
+        if reversed {
+            pairs.reverse();
+        }
+        for (a, b) in pairs {
+            builder.link_nodes(a, b, LINK);
+        }
+
+        let mut nodes = NodesRowTable::new();
+        let mut links = LinksRowTable::new();
+        let map = builder.export(&mut nodes, &mut links);
+
+        let mut solver =
+            XpbdSolver::new(XpbdOptions::default().with_iterations(32).with_mode(mode));
+        solver.track_links(&map.links, &links);
+        solver.set_step_seconds(1.0 / 60.0);
+
+        for _ in 0..300 {
+            nodes.forces_mut_slice()[1..].fill(glam::vec3(1.0, -10.0, 0.0));
+            solver.step(&mut nodes, &mut links);
+        }
+
+        map.nodes
+            .iter()
+            .map(|&node| nodes.current_pos_slice()[nodes.get_indirect(node).unwrap() as usize])
+            .collect()
+    }
+
+    #[test]
+    fn xpbd_coloured_converges_with_gauss_seidel() {
+        let gauss_seidel = settle_truss(SolverMode::GaussSeidel, false);
+        let coloured = settle_truss(SolverMode::Coloured, false);
+
+        // the truss sags under the load
+        assert!(gauss_seidel.last().unwrap().y < -3.0);
+        for (a, b) in gauss_seidel.iter().zip(&coloured) {
+            assert!(a.distance(*b) < 1e-3, "{a} != {b}");
+        }
+    }
+
+    #[test]
+    fn xpbd_jacobi_ignores_link_order() {
+        let forward = settle_truss(SolverMode::JACOBI, false);
+        let reversed = settle_truss(SolverMode::JACOBI, true);
+
+        assert!(forward.last().unwrap().y < -3.0);
+        for (a, b) in forward.iter().zip(&reversed) {
+            assert!(a.distance(*b) < 1e-4, "{a} != {b}");
+        }
+    }
 }